@@ -0,0 +1,613 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::cpu::START_ADDRESS;
+use crate::error::AsmError;
+use crate::instruction::Instruction;
+
+// Deep enough for any sane program, shallow enough to catch files that include each other
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+enum Statement {
+    Instruction { mnemonic: String, operands: Vec<String> },
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+    Constant { name: String, value: String },
+}
+
+struct Item {
+    file: String,
+    line: usize,
+    statement: Statement,
+}
+
+impl Item {
+    fn error(&self, message: String) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.line,
+            message,
+        }
+    }
+}
+
+struct Assembler<'a> {
+    read_include: &'a mut dyn FnMut(&str) -> Result<String, String>,
+    items: Vec<Item>,
+    labels: HashMap<String, u16>,
+    // Wider than an address to detect programs that do not fit in memory
+    address: u32,
+}
+
+/// Assembles source in the mnemonics of Cowgod's technical reference (the same syntax
+/// `chip8-disasm --syntax cowgod` prints) into ROM bytes loaded at 0x200.
+///
+/// Besides instructions, lines can define `label:`s, `NAME equ <value>` constants, `db` bytes and
+/// `dw` big-endian words, and `include "<file>"` other sources. Values are decimal, `#` or `0x`
+/// hexadecimal or `%` or `0b` binary numbers, labels or constants, added or subtracted with
+/// `+` and `-`. Comments start with `;`.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    assemble_with(source, "<source>", &mut |path| {
+        Err(format!("Cannot include {} without a source file", path))
+    })
+}
+
+/// Assembles a file, resolving every include relative to the directory of the file containing it.
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let file = path.display().to_string();
+    let source = fs::read_to_string(path).map_err(|e| AsmError {
+        file: file.clone(),
+        line: 0,
+        message: e.to_string(),
+    })?;
+    assemble_with(&source, &file, &mut |include| {
+        fs::read_to_string(include).map_err(|e| format!("{}: {}", include, e))
+    })
+}
+
+/// Assembles source named `file`, reading included files through `read_include`. Included paths
+/// are joined onto the directory of the file that includes them before being passed on.
+pub fn assemble_with(
+    source: &str,
+    file: &str,
+    read_include: &mut dyn FnMut(&str) -> Result<String, String>,
+) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler {
+        read_include,
+        items: vec![],
+        labels: HashMap::new(),
+        address: START_ADDRESS as u32,
+    };
+    assembler.parse(source, file, 0)?;
+    assembler.emit()
+}
+
+impl Assembler<'_> {
+    /// First pass: splits the source into statements and gives every label its address.
+    fn parse(&mut self, source: &str, file: &str, depth: usize) -> Result<(), AsmError> {
+        for (index, line) in source.lines().enumerate() {
+            let error = |message: String| AsmError {
+                file: file.to_string(),
+                line: index + 1,
+                message,
+            };
+            let mut line = line.split(';').next().unwrap().trim();
+
+            if let Some((label, rest)) = line.split_once(':') {
+                if !is_identifier(label) {
+                    return Err(error(format!("Invalid label '{}'", label)));
+                }
+                if self.labels.insert(label.to_string(), self.address as u16).is_some() {
+                    return Err(error(format!("Label '{}' is defined twice", label)));
+                }
+                line = rest.trim();
+            }
+            if line.is_empty() {
+                continue;
+            }
+
+            let (word, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim();
+            let (length, statement) = match word.to_ascii_lowercase().as_str() {
+                "include" => {
+                    let path = rest
+                        .strip_prefix('"')
+                        .and_then(|path| path.strip_suffix('"'))
+                        .ok_or_else(|| error("Expected include \"<file>\"".to_string()))?;
+                    if depth == MAX_INCLUDE_DEPTH {
+                        return Err(error(format!("Includes nested deeper than {}", depth)));
+                    }
+                    let path = match Path::new(file).parent() {
+                        Some(directory) => directory.join(path).display().to_string(),
+                        None => path.to_string(),
+                    };
+                    let included = (self.read_include)(&path).map_err(error)?;
+                    self.parse(&included, &path, depth + 1)?;
+                    continue;
+                }
+                "db" => {
+                    let values = split_operands(rest);
+                    (values.len() as u32, Statement::Bytes(values))
+                }
+                "dw" => {
+                    let values = split_operands(rest);
+                    (2 * values.len() as u32, Statement::Words(values))
+                }
+                _ => match rest.split_once(char::is_whitespace) {
+                    Some((equ, value)) if equ.eq_ignore_ascii_case("equ") => {
+                        if !is_identifier(word) {
+                            return Err(error(format!("Invalid constant name '{}'", word)));
+                        }
+                        let constant = Statement::Constant {
+                            name: word.to_string(),
+                            value: value.trim().to_string(),
+                        };
+                        (0, constant)
+                    }
+                    _ => {
+                        let operands = split_operands(rest);
+                        let is_long = operands
+                            .get(1)
+                            .is_some_and(|operand| long_operand(operand).is_some());
+                        let length = if is_long { 4 } else { 2 };
+                        let instruction = Statement::Instruction {
+                            mnemonic: word.to_ascii_uppercase(),
+                            operands,
+                        };
+                        (length, instruction)
+                    }
+                },
+            };
+            self.items.push(Item {
+                file: file.to_string(),
+                line: index + 1,
+                statement,
+            });
+            self.address += length;
+            if self.address > 0x10000 {
+                return Err(error("Program does not fit in memory".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Second pass: resolves constants, then encodes every statement.
+    fn emit(self) -> Result<Vec<u8>, AsmError> {
+        let mut symbols: HashMap<String, i64> = self
+            .labels
+            .iter()
+            .map(|(name, address)| (name.clone(), *address as i64))
+            .collect();
+        // Constants can refer to any label, but only to constants defined before them
+        for item in &self.items {
+            if let Statement::Constant { name, value } = &item.statement {
+                let value = evaluate(value, &symbols).map_err(|e| item.error(e))?;
+                if symbols.insert(name.clone(), value).is_some() {
+                    return Err(item.error(format!("'{}' is defined twice", name)));
+                }
+            }
+        }
+
+        let mut output = vec![];
+        for item in &self.items {
+            let result = match &item.statement {
+                Statement::Instruction { mnemonic, operands } => {
+                    encode(mnemonic, operands, &symbols).map(|(instruction, operand)| {
+                        output.extend_from_slice(&instruction.encode().to_be_bytes());
+                        if let Some(operand) = operand {
+                            output.extend_from_slice(&operand.to_be_bytes());
+                        }
+                    })
+                }
+                Statement::Bytes(values) => values.iter().try_for_each(|value| {
+                    output.push(number(value, 0xFF, &symbols)? as u8);
+                    Ok(())
+                }),
+                Statement::Words(values) => values.iter().try_for_each(|value| {
+                    output.extend_from_slice(&number(value, 0xFFFF, &symbols)?.to_be_bytes());
+                    Ok(())
+                }),
+                Statement::Constant { .. } => Ok(()),
+            };
+            result.map_err(|e| item.error(e))?;
+        }
+        Ok(output)
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut characters = name.chars();
+    characters
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && characters.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn split_operands(operands: &str) -> Vec<String> {
+    if operands.is_empty() {
+        return vec![];
+    }
+    operands.split(',').map(|operand| operand.trim().to_string()).collect()
+}
+
+/// The value of a `LONG <value>` operand.
+fn long_operand(operand: &str) -> Option<&str> {
+    let (long, value) = operand.split_once(char::is_whitespace)?;
+    if long.eq_ignore_ascii_case("long") {
+        Some(value.trim())
+    } else {
+        None
+    }
+}
+
+fn register(operand: &str) -> Option<u8> {
+    let digit = operand
+        .strip_prefix('V')
+        .or_else(|| operand.strip_prefix('v'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+fn require_register(operand: &str) -> Result<u8, String> {
+    register(operand).ok_or_else(|| format!("Expected a register, found '{}'", operand))
+}
+
+/// Evaluates a sum of numbers and symbols.
+fn evaluate(expression: &str, symbols: &HashMap<String, i64>) -> Result<i64, String> {
+    let mut total = 0;
+    let mut sign = 1;
+    let mut term = String::new();
+    for character in expression.chars().chain(std::iter::once('+')) {
+        if character != '+' && character != '-' {
+            term.push(character);
+            continue;
+        }
+        let trimmed = term.trim();
+        if trimmed.is_empty() {
+            // A leading sign
+            if total != 0 || character == '+' && !expression.trim().is_empty() {
+                return Err(format!("Invalid expression '{}'", expression));
+            }
+        } else {
+            total = evaluate_term(trimmed, symbols)?
+                .checked_mul(sign)
+                .and_then(|value| value.checked_add(total))
+                .ok_or_else(|| format!("Invalid expression '{}'", expression))?;
+        }
+        sign = if character == '-' { -1 } else { 1 };
+        term.clear();
+    }
+    if expression.trim().is_empty() {
+        return Err("Expected a value".to_string());
+    }
+    Ok(total)
+}
+
+fn evaluate_term(term: &str, symbols: &HashMap<String, i64>) -> Result<i64, String> {
+    let lowercase = term.to_ascii_lowercase();
+    let parsed = if let Some(hex) = lowercase.strip_prefix('#').or(lowercase.strip_prefix("0x")) {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(binary) = lowercase.strip_prefix('%').or(lowercase.strip_prefix("0b")) {
+        i64::from_str_radix(binary, 2)
+    } else if lowercase.starts_with(|c: char| c.is_ascii_digit()) {
+        lowercase.parse()
+    } else {
+        return symbols
+            .get(term)
+            .copied()
+            .ok_or_else(|| format!("Unknown symbol '{}'", term));
+    };
+    parsed.map_err(|_| format!("Invalid number '{}'", term))
+}
+
+/// Evaluates a value that must fit in `max`. Bytes may also be negative.
+fn number(expression: &str, max: u16, symbols: &HashMap<String, i64>) -> Result<u16, String> {
+    let value = evaluate(expression, symbols)?;
+    if max == 0xFF && (-0x80..0).contains(&value) {
+        return Ok(value as u8 as u16);
+    }
+    if !(0..=max as i64).contains(&value) {
+        return Err(format!("{} does not fit in {:#X}", value, max));
+    }
+    Ok(value as u16)
+}
+
+/// Encodes an instruction, and the operand word following `LD I, LONG`.
+fn encode(
+    mnemonic: &str,
+    operands: &[String],
+    symbols: &HashMap<String, i64>,
+) -> Result<(Instruction, Option<u16>), String> {
+    use Instruction::*;
+    let address = |operand: &str| number(operand, 0xFFF, symbols);
+    let byte = |operand: &str| number(operand, 0xFF, symbols).map(|value| value as u8);
+    let nibble = |operand: &str| number(operand, 0xF, symbols).map(|value| value as u8);
+    let operands: Vec<&str> = operands.iter().map(|operand| operand.as_str()).collect();
+    let uppercase: Vec<String> = operands.iter().map(|operand| operand.to_ascii_uppercase()).collect();
+    let uppercase: Vec<&str> = uppercase.iter().map(|operand| operand.as_str()).collect();
+    let registers: Vec<Option<u8>> = operands.iter().map(|operand| register(operand)).collect();
+
+    let instruction = match (mnemonic, operands.as_slice()) {
+        ("CLS", []) => ClearScreen,
+        ("RET", []) => SubroutineReturn,
+        ("SCD", [n]) => ScrollDown(nibble(n)?),
+        ("SCU", [n]) => ScrollUp(nibble(n)?),
+        ("SCR", []) => ScrollRight,
+        ("SCL", []) => ScrollLeft,
+        ("EXIT", []) => Exit,
+        ("LOW", []) => LowResolution,
+        ("HIGH", []) => HighResolution,
+        ("AUDIO", []) => LoadAudioPattern,
+        ("JP", [target]) => Jump(address(target)?),
+        ("JP", [offset, target]) => {
+            let address = address(target)?;
+            // V0 normally, or VX with the offset jump quirk where X is the top address nibble
+            let register_x = (address >> 8) as u8;
+            match register(offset) {
+                Some(0) => {}
+                Some(register) if register == register_x => {}
+                _ => return Err(format!("Expected V0 or V{:X}, found '{}'", register_x, offset)),
+            }
+            JumpWithOffset {
+                register_x,
+                address,
+            }
+        }
+        ("CALL", [target]) => SubroutineCall(address(target)?),
+        ("SE" | "SNE", [x, y]) => {
+            let register_x = require_register(x)?;
+            let equal = mnemonic == "SE";
+            match (registers[1], equal) {
+                (Some(register_y), true) => NoopRegisterEq {
+                    register_x,
+                    register_y,
+                },
+                (Some(register_y), false) => NoopRegisterNotEq {
+                    register_x,
+                    register_y,
+                },
+                (None, true) => NoopImmediateEq {
+                    register: register_x,
+                    value: byte(y)?,
+                },
+                (None, false) => NoopImmediateNotEq {
+                    register: register_x,
+                    value: byte(y)?,
+                },
+            }
+        }
+        ("LD", [x, y]) => match (uppercase[0], uppercase[1], registers[0], registers[1]) {
+            (_, "DT", Some(register), _) => SetVXFromDelayTimer(register),
+            (_, "K", Some(register), _) => GetKey(register),
+            (_, "[I]", Some(register), _) => Load(register),
+            (_, "R", Some(register), _) => LoadFlags(register),
+            (_, _, Some(register_x), Some(register_y)) => ArithmeticSet {
+                register_x,
+                register_y,
+            },
+            (_, _, Some(register), None) => SetVX {
+                register,
+                value: byte(y)?,
+            },
+            ("I", _, _, _) => match long_operand(y) {
+                Some(value) => return Ok((SetILong, Some(number(value, 0xFFFF, symbols)?))),
+                None => SetI(address(y)?),
+            },
+            ("DT", _, _, _) => SetDelayTimerFromVX(require_register(y)?),
+            ("ST", _, _, _) => SetSoundTimerFromVX(require_register(y)?),
+            ("F", _, _, _) => FontCharacter(require_register(y)?),
+            ("HF", _, _, _) => BigFontCharacter(require_register(y)?),
+            ("B", _, _, _) => BcdConversion(require_register(y)?),
+            ("[I]", _, _, _) => Store(require_register(y)?),
+            ("R", _, _, _) => StoreFlags(require_register(y)?),
+            _ => return Err(format!("Unknown LD form '{}, {}'", x, y)),
+        },
+        ("ADD", [x, y]) => match (uppercase[0], registers[0], registers[1]) {
+            ("I", _, _) => AddToIndex(require_register(y)?),
+            (_, Some(register_x), Some(register_y)) => ArithmeticAdd {
+                register_x,
+                register_y,
+            },
+            (_, Some(register), None) => AddVX {
+                register,
+                value: byte(y)?,
+            },
+            _ => return Err(format!("Expected a register, found '{}'", x)),
+        },
+        ("OR" | "AND" | "XOR" | "SUB" | "SUBN" | "SHR" | "SHL", [x, ..]) if operands.len() <= 2 => {
+            let register_x = require_register(x)?;
+            let register_y = match operands.get(1) {
+                Some(y) => require_register(y)?,
+                // Shifts may leave out VY, which only matters with the copy shift quirk
+                None if mnemonic.starts_with("SH") => register_x,
+                None => return Err(format!("{} needs two registers", mnemonic)),
+            };
+            match mnemonic {
+                "OR" => ArithmeticOr {
+                    register_x,
+                    register_y,
+                },
+                "AND" => ArithmeticAnd {
+                    register_x,
+                    register_y,
+                },
+                "XOR" => ArithmeticXor {
+                    register_x,
+                    register_y,
+                },
+                "SUB" => ArithmeticSubtractXY {
+                    register_x,
+                    register_y,
+                },
+                "SUBN" => ArithmeticSubtractYX {
+                    register_x,
+                    register_y,
+                },
+                "SHR" => ArithmeticShiftRight {
+                    register_x,
+                    register_y,
+                },
+                _ => ArithmeticShiftLeft {
+                    register_x,
+                    register_y,
+                },
+            }
+        }
+        ("RND", [x, mask]) => Random {
+            register_x: require_register(x)?,
+            mask: byte(mask)?,
+        },
+        ("DRW", [x, y, n]) => DisplayDraw {
+            register_x: require_register(x)?,
+            register_y: require_register(y)?,
+            n: nibble(n)?,
+        },
+        ("SKP", [x]) => NoopVXDown(require_register(x)?),
+        ("SKNP", [x]) => NoopVXNotDown(require_register(x)?),
+        ("SAVE" | "LOAD", [range]) => {
+            let (x, y) = range
+                .split_once('-')
+                .ok_or_else(|| format!("Expected a register range, found '{}'", range))?;
+            let register_x = require_register(x.trim())?;
+            let register_y = require_register(y.trim())?;
+            if mnemonic == "SAVE" {
+                StoreRange {
+                    register_x,
+                    register_y,
+                }
+            } else {
+                LoadRange {
+                    register_x,
+                    register_y,
+                }
+            }
+        }
+        ("PLANE", [planes]) => SelectPlanes(nibble(planes)?),
+        ("PITCH", [x]) => SetPitch(require_register(x)?),
+        _ => {
+            return Err(format!(
+                "Unknown instruction '{}' with {} operands",
+                mnemonic,
+                operands.len()
+            ))
+        }
+    };
+    Ok((instruction, None))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::asm::assemble;
+    use crate::asm::assemble_with;
+    use crate::cpu::Cpu;
+    use crate::disasm;
+    use crate::disasm::Syntax;
+    use crate::error::AsmError;
+    use crate::instruction::Instruction;
+
+    #[test]
+    fn test_labels_constants_and_data() {
+        let source = "
+            ; Draws a sprite forever
+            COLUMN equ 8 + 2
+            start:  LD V0, COLUMN
+                    LD I, sprite
+            loop:   DRW V0, V1, sprite_end - sprite
+                    JP loop
+            sprite: db #81, %01000010, 0x24
+            sprite_end:
+                    dw start, -1 + 2
+        ";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [
+                0x60, 0x0A, 0xA2, 0x08, 0xD0, 0x13, 0x12, 0x04, 0x81, 0x42, 0x24, 0x02, 0x00, 0x00,
+                0x01,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_include() {
+        let files = HashMap::from([
+            ("src/lib/font.asm", "glyph: db #F0\ninclude \"size.asm\""),
+            ("src/lib/size.asm", "SIZE equ 1"),
+        ]);
+        let source = "LD I, glyph\nDRW V0, V0, SIZE\ninclude \"lib/font.asm\"";
+        // Nested includes are relative to the file that includes them
+        let rom = assemble_with(source, "src/main.asm", &mut |path| {
+            files.get(path).map(|source| source.to_string()).ok_or(path.to_string())
+        });
+        assert_eq!(rom.unwrap(), [0xA2, 0x04, 0xD0, 0x01, 0xF0]);
+
+        let mut recursive = |_: &str| Ok("include \"self.asm\"".to_string());
+        assert!(assemble_with("include \"self.asm\"", "self.asm", &mut recursive).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |message: &str, line| AsmError {
+            file: "<source>".to_string(),
+            line,
+            message: message.to_string(),
+        };
+        assert_eq!(
+            assemble("CLS\nJP nowhere").err(),
+            Some(error("Unknown symbol 'nowhere'", 2))
+        );
+        assert_eq!(assemble("ADD V0, 256").err(), Some(error("256 does not fit in 0xFF", 1)));
+        assert_eq!(
+            assemble("a:\na:").err(),
+            Some(error("Label 'a' is defined twice", 2))
+        );
+        assert!(assemble("MOV V0, V1").is_err());
+        assert_eq!(
+            assemble("LD I, 0x7FFFFFFFFFFFFFFF + 1").err(),
+            Some(error("Invalid expression '0x7FFFFFFFFFFFFFFF + 1'", 1))
+        );
+        assert_eq!(
+            assemble("A equ 0x7FFFFFFFFFFFFFFF\nLD I, A + A").err(),
+            Some(error("Invalid expression 'A + A'", 2))
+        );
+    }
+
+    #[test]
+    fn test_round_trip_through_decode() {
+        let instructions = [
+            "CLS", "RET", "SCD 3", "SCU 4", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP #2AB",
+            "CALL #345", "SE V1, #22", "SNE V1, #22", "SE V1, V2", "SNE V1, V2", "LD V3, #44",
+            "ADD V3, #44", "LD V1, V2", "OR V1, V2", "AND V1, V2", "XOR V1, V2", "ADD V1, V2",
+            "SUB V1, V2", "SHR V1, V2", "SUBN V1, V2", "SHL V1, V2", "LD I, #123",
+            "LD I, LONG #1234", "JP V0, #0AB", "JP V3, #3AB", "RND V4, #0F", "DRW V1, V2, 15",
+            "SKP V5", "SKNP V5", "LD V6, DT", "LD V6, K", "LD DT, V6", "LD ST, V6", "ADD I, V6",
+            "LD F, V7", "LD HF, V7", "LD B, V7", "LD [I], V8", "LD V8, [I]", "SAVE V1-V4",
+            "LOAD V4-V1", "PLANE 3", "AUDIO", "PITCH V9", "LD R, VA", "LD VA, R",
+        ];
+        let rom = assemble(&instructions.join("\n")).unwrap();
+
+        // Every mnemonic decodes back to a distinct instruction that encodes to the same word
+        let mut decoded: Vec<Instruction> = vec![];
+        let mut address = 0;
+        while address < rom.len() {
+            let raw = u16::from_be_bytes([rom[address], rom[address + 1]]);
+            let instruction = Cpu::decode_at(raw, address as u16).unwrap();
+            assert_eq!(instruction.encode(), raw);
+            assert!(!decoded.contains(&instruction), "{:?} twice", instruction);
+            decoded.push(instruction);
+            address += if let Instruction::SetILong = instruction { 4 } else { 2 };
+        }
+        assert_eq!(decoded.len(), instructions.len());
+
+        // The Cowgod listing of the ROM is the source it came from
+        let listing: Vec<String> = disasm::disassemble(&rom, Syntax::Cowgod)
+            .into_iter()
+            .map(|line| line.text)
+            .collect();
+        assert_eq!(listing, instructions);
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::asm;
+use crate::cpu::Cpu;
+use crate::cpu::Memory;
+use crate::display::VRAM;
+use crate::dump;
+use crate::movie::checksum;
+use crate::octo;
+use crate::quirks::Platform;
+
+// Enough for test ROMs to finish drawing their results within a few frames
+pub const INSTRUCTIONS_PER_FRAME: u32 = 1000;
+// Fixed so the screen only depends on the ROM, the quirks and the number of frames
+const SEED: u64 = 0;
+
+/// A test ROM and the hash of the screen it shows after running for a number of frames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+    pub name: String,
+    // Relative to the suite file
+    pub rom: String,
+    pub platform: Platform,
+    pub frames: u64,
+    // None until a known good run is recorded
+    pub expected: Option<u64>,
+    // Whether the case may be missing or unrecorded without failing
+    pub optional: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    // The screen differs from the recorded one
+    Fail { actual: u64, screen: String },
+    // Nothing recorded to compare the screen with
+    Unrecorded { actual: u64, screen: String },
+    // The ROM is not on disk, such as third-party suites that are not part of the repository
+    Missing,
+    // The ROM failed to build or halted on an unknown instruction
+    Error(String),
+}
+
+impl Outcome {
+    /// Whether the outcome fails the suite. Missing ROMs and unrecorded screens only pass for
+    /// cases marked optional.
+    pub fn is_failure(&self, case: &Case) -> bool {
+        match self {
+            Outcome::Pass => false,
+            Outcome::Unrecorded { .. } | Outcome::Missing => !case.optional,
+            Outcome::Fail { .. } | Outcome::Error(_) => true,
+        }
+    }
+}
+
+/// Parses a suite file. Each line that is not empty or a `#` comment describes a case:
+///
+/// ```text
+/// <name> <rom> <platform> <frames> <VRAM hash in hex, or - if not recorded> [optional]
+/// ```
+pub fn parse_suite(text: &str) -> Result<Vec<Case>, String> {
+    let mut cases = vec![];
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: String| format!("Line {}: {}", index + 1, message);
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (name, rom, platform, frames, expected, optional) = match fields[..] {
+            [name, rom, platform, frames, expected] => (name, rom, platform, frames, expected, false),
+            [name, rom, platform, frames, expected, "optional"] => {
+                (name, rom, platform, frames, expected, true)
+            }
+            [.., flag] if fields.len() == 6 => {
+                return Err(error(format!("Expected 'optional', found '{}'", flag)))
+            }
+            _ => return Err(error(format!("Expected 5 or 6 fields, found {}", fields.len()))),
+        };
+        let expected = match expected {
+            "-" => None,
+            hash => Some(
+                u64::from_str_radix(hash, 16)
+                    .map_err(|_| error(format!("Invalid hash '{}'", hash)))?,
+            ),
+        };
+        cases.push(Case {
+            name: name.to_string(),
+            rom: rom.to_string(),
+            platform: platform.parse().map_err(error)?,
+            frames: frames
+                .parse()
+                .map_err(|_| error(format!("Invalid frame count '{}'", frames)))?,
+            expected,
+            optional,
+        });
+    }
+    Ok(cases)
+}
+
+/// Replaces the recorded hashes of the named cases, keeping the rest of the suite file as is.
+pub fn update_suite(text: &str, hashes: &HashMap<String, u64>) -> String {
+    let mut output = String::new();
+    for line in text.lines() {
+        let name = line.split_whitespace().next().unwrap_or("");
+        match (hashes.get(name), field_span(line, 4)) {
+            (Some(hash), Some((start, end))) if !name.starts_with('#') => {
+                output += &format!("{}{:016x}{}", &line[..start], hash, &line[end..]);
+            }
+            _ => output += line,
+        }
+        output.push('\n');
+    }
+    output
+}
+
+/// Byte range of the whitespace-separated field at `index` in a line.
+fn field_span(line: &str, index: usize) -> Option<(usize, usize)> {
+    let mut span = (0, 0);
+    for _ in 0..=index {
+        let start = span.1 + line[span.1..].find(|c: char| !c.is_whitespace())?;
+        let length = line[start..].find(char::is_whitespace).unwrap_or(line.len() - start);
+        span = (start, start + length);
+    }
+    Some(span)
+}
+
+/// Hashes the resolution and every pixel of the screen.
+pub fn vram_hash(vram: &VRAM) -> u64 {
+    let mut data = vec![vram.width, vram.height];
+    for y in 0..vram.height {
+        for x in 0..vram.width {
+            data.push(vram.get_pixel(x, y));
+        }
+    }
+    checksum(&data)
+}
+
+/// Reads a ROM, building Octo (`.8o`) and assembly (`.asm`) sources first.
+pub fn load_rom(path: &Path) -> Result<Vec<u8>, String> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("8o") => octo::compile_file(path).map_err(|e| e.to_string()),
+        Some("asm") => asm::assemble_file(path).map_err(|e| e.to_string()),
+        _ => fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+/// Runs a ROM for a number of 60 Hz frames with the quirks of the platform.
+pub fn run(rom: Vec<u8>, platform: Platform, frames: u64) -> Result<Cpu, String> {
+    let mut cpu = Cpu::with_seed(Memory::new(rom)?, platform.quirks(), SEED);
+    for _ in 0..frames {
+        for _ in 0..INSTRUCTIONS_PER_FRAME {
+            if cpu.is_waiting_for_vblank() || cpu.has_exited() {
+                break;
+            }
+            cpu.step().map_err(|e| e.to_string())?;
+        }
+        cpu.tick_timers();
+    }
+    Ok(cpu)
+}
+
+/// Runs a case, with its ROM relative to `directory`, and compares the screen to the recorded one.
+pub fn check(case: &Case, directory: &Path) -> Outcome {
+    let path = directory.join(&case.rom);
+    if !path.exists() {
+        return Outcome::Missing;
+    }
+    let cpu = match load_rom(&path).and_then(|rom| run(rom, case.platform, case.frames)) {
+        Ok(cpu) => cpu,
+        Err(error) => return Outcome::Error(error),
+    };
+    let actual = vram_hash(cpu.vram());
+    let screen = dump::format_vram(cpu.vram());
+    match case.expected {
+        Some(expected) if expected == actual => Outcome::Pass,
+        Some(_) => Outcome::Fail { actual, screen },
+        None => Outcome::Unrecorded { actual, screen },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::compat::parse_suite;
+    use crate::compat::run;
+    use crate::compat::update_suite;
+    use crate::compat::vram_hash;
+    use crate::compat::Case;
+    use crate::compat::Outcome;
+    use crate::quirks::Platform;
+
+    const SUITE: &str = "\
+# name  rom        platform    frames  hash              optional
+logo    logo.ch8   cosmac-vip  10      -                 optional
+
+flags   flags.asm  schip-1.1   20      00000000000000ff
+";
+
+    #[test]
+    fn test_parse_and_update_suite() {
+        let cases = parse_suite(SUITE).unwrap();
+        assert_eq!(
+            cases,
+            [
+                Case {
+                    name: "logo".to_string(),
+                    rom: "logo.ch8".to_string(),
+                    platform: Platform::CosmacVip,
+                    frames: 10,
+                    expected: None,
+                    optional: true,
+                },
+                Case {
+                    name: "flags".to_string(),
+                    rom: "flags.asm".to_string(),
+                    platform: Platform::Schip11,
+                    frames: 20,
+                    expected: Some(0xFF),
+                    optional: false,
+                },
+            ]
+        );
+
+        let updated = update_suite(SUITE, &HashMap::from([("logo".to_string(), 0xABC)]));
+        assert_eq!(
+            updated.lines().nth(1),
+            Some("logo    logo.ch8   cosmac-vip  10      0000000000000abc                 optional")
+        );
+        assert_eq!(parse_suite(&updated).unwrap()[0].expected, Some(0xABC));
+
+        assert!(parse_suite("logo logo.ch8 cosmac-vip 10").is_err());
+        assert!(parse_suite("logo logo.ch8 vip 10 -").is_err());
+        assert!(parse_suite("logo logo.ch8 cosmac-vip 10 - skip").is_err());
+    }
+
+    #[test]
+    fn test_optional_cases() {
+        let cases = parse_suite(SUITE).unwrap();
+        let (optional, required) = (&cases[0], &cases[1]);
+        assert!(!Outcome::Missing.is_failure(optional));
+        assert!(Outcome::Missing.is_failure(required));
+        let unrecorded = Outcome::Unrecorded {
+            actual: 0,
+            screen: String::new(),
+        };
+        assert!(!unrecorded.is_failure(optional));
+        assert!(unrecorded.is_failure(required));
+        assert!(Outcome::Error("Halted".to_string()).is_failure(optional));
+        assert!(!Outcome::Pass.is_failure(required));
+    }
+
+    #[test]
+    fn test_screens_differ() {
+        // Draws the font sprite of 0 in the corner
+        let rom = vec![0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06];
+        let blank = run(vec![0x12, 0x00], Platform::Modern, 1).unwrap();
+        let drawn = run(rom, Platform::Modern, 1).unwrap();
+        assert_ne!(vram_hash(blank.vram()), vram_hash(drawn.vram()));
+        assert!(run(vec![0xFF, 0xFF], Platform::Modern, 1).is_err());
+    }
+}
@@ -0,0 +1,1725 @@
+use rand::Rng;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::error::DecodeError;
+use crate::error::DecodeErrorKind;
+use crate::error::SavestateError;
+use crate::instruction::Instruction;
+use crate::savestate;
+use crate::savestate::StateReader;
+use crate::savestate::StateWriter;
+
+use super::display::PLANE_COUNT;
+use super::display::VRAM;
+
+use super::keypad::Keycode;
+use super::keypad::Keypad;
+use super::quirks::Quirks;
+
+const FONTS: &[u8] = &[
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80  // F
+];
+
+const BIG_FONTS: &[u8] = &[
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
+];
+
+pub struct Memory {
+    data: Vec<u8>,
+    font_start_address: u16,
+    big_font_start_address: u16,
+}
+
+// XO-CHIP extends the address space to 64 KiB
+const MEMORY_SIZE: usize = 0x10000;
+/// Address ROMs are loaded at and start executing from
+pub const START_ADDRESS: u16 = 0x200;
+
+impl Memory {
+    pub fn new(rom_data: Vec<u8>) -> Result<Memory, String> {
+        let mut memory: Vec<u8> = vec![0; MEMORY_SIZE];
+
+        // Write ROM to memory
+        let start_address = START_ADDRESS as usize;
+        if rom_data.len() > MEMORY_SIZE - start_address {
+            return Err(format!(
+                "ROM is {} bytes, at most {} bytes fit in memory",
+                rom_data.len(),
+                MEMORY_SIZE - start_address
+            ));
+        }
+        for (i, byte) in rom_data.iter().enumerate() {
+            memory[start_address + i] = *byte;
+        }
+
+        // Write fonts to memory
+        let font_start = 0x0050;
+        for (i, byte) in FONTS.iter().enumerate() {
+            memory[font_start + i] = *byte;
+        }
+        let big_font_start = font_start + FONTS.len();
+        for (i, byte) in BIG_FONTS.iter().enumerate() {
+            memory[big_font_start + i] = *byte;
+        }
+        Ok(Memory {
+            data: memory,
+            font_start_address: font_start as u16,
+            big_font_start_address: big_font_start as u16,
+        })
+    }
+
+    pub fn get(&self, address: u16) -> u8 {
+        self.data[address as usize]
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Reads the big-endian word at the address, wrapping around the end of memory
+    pub fn get_word(&self, address: u16) -> u16 {
+        let first_byte = self.get(address);
+        let second_byte = self.get(address.wrapping_add(1));
+        ((first_byte as u16) << 8) | second_byte as u16
+    }
+
+    fn set(& mut self, address: u16, value: u8) {
+        self.data[address as usize] = value;
+    }
+
+    fn get_font_address(&self, character: u8) -> u16 {
+        // Each font is 5 bytes
+        self.font_start_address + (character as u16) * 5
+    }
+
+    fn get_big_font_address(&self, character: u8) -> u16 {
+        // Each big font is 10 bytes
+        self.big_font_start_address + (character as u16) * 10
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.data);
+        writer.write_u16(self.font_start_address);
+        writer.write_u16(self.big_font_start_address);
+    }
+
+    fn load_state(reader: &mut StateReader) -> Result<Memory, SavestateError> {
+        let data = reader.read_bytes()?;
+        if data.len() != MEMORY_SIZE {
+            return Err(SavestateError::Invalid(format!("Memory is {} bytes", data.len())));
+        }
+        Ok(Memory {
+            data: data.to_vec(),
+            font_start_address: reader.read_u16()?,
+            big_font_start_address: reader.read_u16()?,
+        })
+    }
+}
+
+// Playback rate of the audio pattern at the default pitch of 64
+const BASE_PLAYBACK_RATE: f32 = 4000.0;
+const DEFAULT_PITCH: u8 = 64;
+
+/// What the sound output should currently be playing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioState {
+    pub playing: bool,
+    // 128 1-bit samples loaded by XO-CHIP F002, or None for the default beep
+    pub pattern: Option<[u8; 16]>,
+    pub pitch: u8,
+}
+
+impl AudioState {
+    /// Number of pattern bits played per second
+    pub fn playback_rate(&self) -> f32 {
+        BASE_PLAYBACK_RATE * 2f32.powf((self.pitch as f32 - DEFAULT_PITCH as f32) / 48.0)
+    }
+}
+
+// Levels of nested subroutine calls, as on SUPER-CHIP
+pub const CALL_STACK_SIZE: usize = 16;
+
+pub struct Cpu {
+    pc: u16,
+    // Address and opcode of the instruction most recently fetched, used for error reporting
+    instruction_pc: u16,
+    opcode: u16,
+    memory: Memory,
+    registers: Vec<u8>,
+    index_register: u16,
+    vram: VRAM,
+    call_stack: Vec<u16>,
+    quirks: Quirks,
+    sound_timer: u8,
+    delay_timer: u8,
+    keypad: Keypad,
+    // SUPER-CHIP RPL user flags, persisted by FX75 and restored by FX85
+    rpl_flags: Vec<u8>,
+    exited: bool,
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
+    // Set after drawing with the display wait quirk, cleared by the next 60 Hz tick
+    waiting_for_vblank: bool,
+    // Source for CXNN, seeded so that runs can be reproduced
+    seed: u64,
+    rng: ChaCha8Rng,
+}
+
+fn get_nibble_from_right(i: u8, value: u16) -> u8 {
+    let shift = 4 * i;
+    ((value >> shift) & 0x000F) as u8
+}
+
+fn register_range(register_x: u8, register_y: u8) -> Box<dyn Iterator<Item = u8>> {
+    if register_x <= register_y {
+        Box::new(register_x..=register_y)
+    } else {
+        Box::new((register_y..=register_x).rev())
+    }
+}
+
+/// Hundreds, tens and ones of the value, with leading zeros
+fn get_digits(value: u8) -> [u8; 3] {
+    [value / 100, value / 10 % 10, value % 10]
+}
+
+impl Cpu {
+    pub fn new(memory: Memory, quirks: Quirks) -> Cpu {
+        Cpu::with_seed(memory, quirks, rand::random())
+    }
+
+    /// Creates a CPU whose CXNN random numbers are determined by the seed.
+    pub fn with_seed(memory: Memory, quirks: Quirks, seed: u64) -> Cpu {
+        Cpu {
+            pc: START_ADDRESS,
+            instruction_pc: START_ADDRESS,
+            opcode: 0,
+            memory,
+            registers: vec![0; 16],
+            index_register: 0,
+            vram: VRAM::new(),
+            call_stack: vec![],
+            quirks,
+            sound_timer: 0,
+            delay_timer: 0,
+            keypad: Keypad::new(),
+            rpl_flags: vec![0; 16],
+            exited: false,
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            waiting_for_vblank: false,
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn vram(&self) -> &VRAM {
+        &self.vram
+    }
+
+    pub fn keypad(&mut self) -> & mut Keypad {
+        & mut self.keypad
+    }
+
+    /// Whether the CPU is blocked until the next call to `tick_timers`, in which case no
+    /// instructions should be executed
+    pub fn is_waiting_for_vblank(&self) -> bool {
+        self.waiting_for_vblank
+    }
+
+    /// Whether the program has run the SUPER-CHIP exit instruction
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn index_register(&self) -> u16 {
+        self.index_register
+    }
+
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    pub fn call_stack(&self) -> &[u16] {
+        &self.call_stack
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// Moves execution elsewhere, for debuggers
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn set_index_register(&mut self, value: u16) {
+        self.index_register = value;
+    }
+
+    pub fn set_memory(&mut self, address: u16, value: u8) {
+        self.memory.set(address, value);
+    }
+
+    pub fn fetch(& mut self) -> u16 {
+        let result = self.memory.get_word(self.pc);
+        self.instruction_pc = self.pc;
+        self.pc = self.pc.wrapping_add(2);
+
+        self.opcode = result;
+        result
+    }
+
+    /// Fetches, decodes and executes a single instruction.
+    pub fn step(& mut self) -> Result<(), DecodeError> {
+        let raw_instruction = self.fetch();
+        let instruction = self.decode(raw_instruction)?;
+        self.execute(instruction)
+    }
+
+    /// Decodes an opcode fetched from the address of the most recently fetched instruction.
+    pub fn decode(&self, raw: u16) -> Result<Instruction, DecodeError> {
+        Cpu::decode_at(raw, self.instruction_pc)
+    }
+
+    /// Decodes an opcode, reporting `pc` as its address if it is not a known instruction.
+    pub fn decode_at(raw: u16, pc: u16) -> Result<Instruction, DecodeError> {
+        let error = |kind| Err(DecodeError::new(kind, raw, pc));
+        let instruction = match raw {
+            0x00E0 => Instruction::ClearScreen,
+            0x00EE => Instruction::SubroutineReturn,
+            0x00C0..=0x00CF => Instruction::ScrollDown(get_nibble_from_right(0, raw)),
+            0x00D0..=0x00DF => Instruction::ScrollUp(get_nibble_from_right(0, raw)),
+            0x00FB => Instruction::ScrollRight,
+            0x00FC => Instruction::ScrollLeft,
+            0x00FD => Instruction::Exit,
+            0x00FE => Instruction::LowResolution,
+            0x00FF => Instruction::HighResolution,
+            0x1000..=0x1FFF => Instruction::Jump(raw & 0x0FFF),
+            0x2000..=0x2FFF => Instruction::SubroutineCall(raw & 0x0FFF),
+            0x6000..=0x6FFF => Instruction::SetVX {
+                register: get_nibble_from_right(2, raw),
+                value: (raw & 0x00FF) as u8,
+            },
+            0x7000..=0x7FFF => Instruction::AddVX {
+                register: get_nibble_from_right(2, raw),
+                value: (raw & 0x00FF) as u8,
+            },
+            0xA000..=0xAFFF => Instruction::SetI(raw & 0x0FFF),
+            0xD000..=0xDFFF => Instruction::DisplayDraw {
+                register_x: get_nibble_from_right(2, raw),
+                register_y: get_nibble_from_right(1, raw),
+                n: get_nibble_from_right(0, raw),
+            },
+            0x3000..=0x3FFF => Instruction::NoopImmediateEq {
+                register: get_nibble_from_right(2, raw),
+                value: (raw & 0x00FF) as u8,
+            },
+            0x4000..=0x4FFF => Instruction::NoopImmediateNotEq {
+                register: get_nibble_from_right(2, raw),
+                value: (raw & 0x00FF) as u8,
+            },
+            0x5000..=0x5FFF => {
+                let register_x = get_nibble_from_right(2, raw);
+                let register_y = get_nibble_from_right(1, raw);
+                match get_nibble_from_right(0, raw) {
+                    0x00 => Instruction::NoopRegisterEq { register_x, register_y },
+                    0x02 => Instruction::StoreRange { register_x, register_y },
+                    0x03 => Instruction::LoadRange { register_x, register_y },
+                    _ => return error(DecodeErrorKind::UnknownInstruction),
+                }
+            },
+            0x9000..=0x9FFF if raw & 0x000F == 0 => Instruction::NoopRegisterNotEq {
+                register_x: get_nibble_from_right(2, raw),
+                register_y: get_nibble_from_right(1, raw),
+            },
+            0x8000..=0x8FFF => {
+                let masked = get_nibble_from_right(0, raw);
+                match masked {
+                    0x00 => Instruction::ArithmeticSet {
+                        register_x: get_nibble_from_right(2, raw),
+                        register_y: get_nibble_from_right(1, raw),
+                    },
+                    0x01 => Instruction::ArithmeticOr {
+                        register_x: get_nibble_from_right(2, raw),
+                        register_y: get_nibble_from_right(1, raw),
+                    },
+                    0x02 => Instruction::ArithmeticAnd {
+                        register_x: get_nibble_from_right(2, raw),
+                        register_y: get_nibble_from_right(1, raw),
+                    },
+                    0x03 => Instruction::ArithmeticXor {
+                        register_x: get_nibble_from_right(2, raw),
+                        register_y: get_nibble_from_right(1, raw),
+                    },
+                    0x04 => Instruction::ArithmeticAdd {
+                        register_x: get_nibble_from_right(2, raw),
+                        register_y: get_nibble_from_right(1, raw),
+                    },
+                    0x05 => Instruction::ArithmeticSubtractXY {
+                        register_x: get_nibble_from_right(2, raw),
+                        register_y: get_nibble_from_right(1, raw),
+                    },
+                    0x07 => Instruction::ArithmeticSubtractYX {
+                        register_x: get_nibble_from_right(2, raw),
+                        register_y: get_nibble_from_right(1, raw),
+                    },
+                    0x06 => Instruction::ArithmeticShiftRight {
+                        register_x: get_nibble_from_right(2, raw),
+                        register_y: get_nibble_from_right(1, raw),
+                    },
+                    0x0E => Instruction::ArithmeticShiftLeft {
+                        register_x: get_nibble_from_right(2, raw),
+                        register_y: get_nibble_from_right(1, raw),
+                    },
+                    _ => return error(DecodeErrorKind::UnknownArithmeticInstruction),
+                }
+            },
+            0xB000..=0xBFFF => Instruction::JumpWithOffset {
+                register_x: get_nibble_from_right(2, raw),
+                address: raw & 0x0FFF,
+            },
+            0xC000..=0xCFFF => Instruction::Random {
+                register_x: get_nibble_from_right(2, raw),
+                mask: (raw & 0x00FF) as u8,
+            },
+            0xE000..=0xEFFF => {
+                let lsb_masked = raw & 0x00FF;
+                match lsb_masked {
+                    0x9E => Instruction::NoopVXDown(get_nibble_from_right(2, raw)),
+                    0xA1 => Instruction::NoopVXNotDown(get_nibble_from_right(2, raw)),
+                    _ => return error(DecodeErrorKind::UnknownEPrefixInstruction),
+                }
+            },
+            0xF000..=0xFFFF => {
+                let lsb_masked = raw & 0x00FF;
+                match lsb_masked {
+                    0x00 if raw == 0xF000 => Instruction::SetILong,
+                    0x01 => Instruction::SelectPlanes(get_nibble_from_right(2, raw)),
+                    0x29 => Instruction::FontCharacter(get_nibble_from_right(2, raw)),
+                    0x30 => Instruction::BigFontCharacter(get_nibble_from_right(2, raw)),
+                    0x33 => Instruction::BcdConversion(get_nibble_from_right(2, raw)),
+                    0x55 => Instruction::Store(get_nibble_from_right(2, raw)),
+                    0x65 => Instruction::Load(get_nibble_from_right(2, raw)),
+                    0x1E => Instruction::AddToIndex(get_nibble_from_right(2, raw)),
+                    0x07 => Instruction::SetVXFromDelayTimer(get_nibble_from_right(2, raw)),
+                    0x15 => Instruction::SetDelayTimerFromVX(get_nibble_from_right(2, raw)),
+                    0x18 => Instruction::SetSoundTimerFromVX(get_nibble_from_right(2, raw)),
+                    0x0A => Instruction::GetKey(get_nibble_from_right(2, raw)),
+                    0x02 if raw == 0xF002 => Instruction::LoadAudioPattern,
+                    0x3A => Instruction::SetPitch(get_nibble_from_right(2, raw)),
+                    0x75 => Instruction::StoreFlags(get_nibble_from_right(2, raw)),
+                    0x85 => Instruction::LoadFlags(get_nibble_from_right(2, raw)),
+                    _ => return error(DecodeErrorKind::UnknownFPrefixInstruction),
+                }
+            },
+            _ => return error(DecodeErrorKind::UnknownInstruction),
+        };
+        Ok(instruction)
+    }
+
+    pub fn execute(& mut self, instruction: Instruction) -> Result<(), DecodeError> {
+        match instruction {
+            Instruction::ClearScreen => self.vram.clear(),
+            Instruction::ScrollDown(rows) => self.vram.scroll_down(rows),
+            Instruction::ScrollUp(rows) => self.vram.scroll_up(rows),
+            Instruction::ScrollRight => self.vram.scroll_right(4),
+            Instruction::ScrollLeft => self.vram.scroll_left(4),
+            Instruction::Exit => {
+                self.exited = true;
+                // Keep executing the exit instruction until the frontend stops
+                self.pc = self.pc.wrapping_sub(2);
+            },
+            Instruction::LowResolution => self.vram.set_hires(false),
+            Instruction::HighResolution => self.vram.set_hires(true),
+            Instruction::Jump(address) => {
+                self.pc = address;
+            },
+            Instruction::SubroutineReturn => {
+                self.pc = match self.call_stack.pop() {
+                    Some(address) => address,
+                    None => return Err(self.error(DecodeErrorKind::StackUnderflow)),
+                };
+            },
+            Instruction::SubroutineCall(address) => {
+                if self.call_stack.len() == CALL_STACK_SIZE {
+                    return Err(self.error(DecodeErrorKind::StackOverflow));
+                }
+                self.call_stack.push(self.pc);
+                self.pc = address;
+            },
+            Instruction::SetVX { register, value } => {
+                self.set_register(register, value);
+            },
+            Instruction::AddVX { register, value } => {
+                let existing_value = self.get_register(register);
+                self.set_register(register, existing_value.wrapping_add(value));
+            },
+            Instruction::SetI(value) => {
+                self.index_register = value;
+            },
+            Instruction::SetILong => {
+                self.index_register = self.memory.get_word(self.pc);
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::DisplayDraw { register_x, register_y, n } => {
+                self.draw_sprite(register_x, register_y, n);
+                self.waiting_for_vblank = self.quirks.display_wait;
+            },
+            Instruction::NoopImmediateEq { register, value } => {
+                if self.get_register(register) == value {
+                    self.do_noop();
+                }
+            },
+            Instruction::NoopImmediateNotEq { register, value } => {
+                if self.get_register(register) != value {
+                    self.do_noop();
+                }
+            },
+            Instruction::NoopRegisterEq { register_x, register_y } => {
+                if self.get_register(register_x) == self.get_register(register_y) {
+                    self.do_noop();
+                }
+            },
+            Instruction::NoopRegisterNotEq { register_x, register_y } => {
+                if self.get_register(register_x) != self.get_register(register_y) {
+                    self.do_noop();
+                }
+            },
+            Instruction::ArithmeticSet { register_x, register_y } => {
+                let value_y = self.get_register(register_y);
+                self.set_register(register_x, value_y);
+            },
+            Instruction::ArithmeticOr { register_x, register_y } => {
+                let value_x = self.get_register(register_x);
+                let value_y = self.get_register(register_y);
+                let flag = self.quirks.vf_reset.then_some(0);
+                self.set_result_and_flag(register_x, value_x | value_y, flag);
+            },
+            Instruction::ArithmeticAnd { register_x, register_y } => {
+                let value_x = self.get_register(register_x);
+                let value_y = self.get_register(register_y);
+                let flag = self.quirks.vf_reset.then_some(0);
+                self.set_result_and_flag(register_x, value_x & value_y, flag);
+            },
+            Instruction::ArithmeticXor { register_x, register_y } => {
+                let value_x = self.get_register(register_x);
+                let value_y = self.get_register(register_y);
+                let flag = self.quirks.vf_reset.then_some(0);
+                self.set_result_and_flag(register_x, value_x ^ value_y, flag);
+            },
+            Instruction::ArithmeticAdd { register_x, register_y } => {
+                let value_x = self.get_register(register_x);
+                let value_y = self.get_register(register_y);
+                let (result, did_overflow) = value_x.overflowing_add(value_y);
+                self.set_result_and_flag(register_x, result, Some(did_overflow as u8));
+            },
+            Instruction::ArithmeticSubtractXY { register_x, register_y } => {
+                let value_x = self.get_register(register_x);
+                let value_y = self.get_register(register_y);
+                // VF is 1 when there is no borrow, including when both values are equal
+                let no_borrow = value_x >= value_y;
+                self.set_result_and_flag(register_x, value_x.wrapping_sub(value_y), Some(no_borrow as u8));
+            },
+            Instruction::ArithmeticSubtractYX { register_x, register_y } => {
+                let value_x = self.get_register(register_x);
+                let value_y = self.get_register(register_y);
+                let no_borrow = value_y >= value_x;
+                self.set_result_and_flag(register_x, value_y.wrapping_sub(value_x), Some(no_borrow as u8));
+            },
+            Instruction::ArithmeticShiftRight { register_x, register_y } => {
+                let value = self.get_shift_source(register_x, register_y);
+                self.set_result_and_flag(register_x, value >> 1, Some(value & 0x01));
+            },
+            Instruction::ArithmeticShiftLeft { register_x, register_y } => {
+                let value = self.get_shift_source(register_x, register_y);
+                self.set_result_and_flag(register_x, value << 1, Some(value >> 7));
+            },
+            Instruction::Store(inclusive_end_register_x) => {
+                let start_address = self.index_register;
+                for i in 0..=inclusive_end_register_x {
+                    let value = self.get_register(i);
+                    self.memory.set(start_address.wrapping_add(i as u16), value);
+                }
+                self.increment_index_after_memory_access(inclusive_end_register_x);
+            },
+            Instruction::Load(inclusive_end_register_x) => {
+                let start_address = self.index_register;
+                for i in 0..=inclusive_end_register_x {
+                    let value = self.memory.get(start_address.wrapping_add(i as u16));
+                    self.set_register(i, value);
+                }
+                self.increment_index_after_memory_access(inclusive_end_register_x);
+            },
+            Instruction::StoreRange { register_x, register_y } => {
+                // Registers are saved in order from X to Y, which may be descending
+                for (i, register) in register_range(register_x, register_y).enumerate() {
+                    let value = self.get_register(register);
+                    self.memory.set(self.index_register.wrapping_add(i as u16), value);
+                }
+            },
+            Instruction::LoadRange { register_x, register_y } => {
+                for (i, register) in register_range(register_x, register_y).enumerate() {
+                    let value = self.memory.get(self.index_register.wrapping_add(i as u16));
+                    self.set_register(register, value);
+                }
+            },
+            Instruction::BcdConversion(register_x) => {
+                let value_x = self.get_register(register_x);
+                let digits = get_digits(value_x);
+
+                for (i, digit) in digits.into_iter().enumerate() {
+                    self.memory.set(self.index_register.wrapping_add(i as u16), digit);
+                }
+            },
+            Instruction::FontCharacter(register_x) => {
+                // Only last nibble relevant
+                let character = self.get_register(register_x) & 0x0F;
+                self.index_register = self.memory.get_font_address(character);
+            },
+            Instruction::BigFontCharacter(register_x) => {
+                let character = self.get_register(register_x) & 0x0F;
+                self.index_register = self.memory.get_big_font_address(character);
+            },
+            Instruction::AddToIndex(register_x) => {
+                // VF is left alone: only the Amiga interpreter set it when I passed 0xFFF, and with
+                // 64 KiB of memory XO-CHIP programs index above that as a matter of course
+                self.index_register = self.index_register.wrapping_add(self.get_register(register_x) as u16);
+            },
+            Instruction::SetVXFromDelayTimer(register_x) => {
+                self.set_register(register_x, self.delay_timer);
+            },
+            Instruction::SetDelayTimerFromVX(register_x) => {
+                self.delay_timer = self.get_register(register_x);
+            },
+            Instruction::SetSoundTimerFromVX(register_x) => {
+                self.sound_timer = self.get_register(register_x);
+            },
+            Instruction::LoadAudioPattern => {
+                let mut pattern = [0; 16];
+                for (i, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.memory.get(self.index_register.wrapping_add(i as u16));
+                }
+                self.audio_pattern = Some(pattern);
+            },
+            Instruction::SetPitch(register_x) => {
+                self.pitch = self.get_register(register_x);
+            },
+            Instruction::NoopVXDown(register_x) => {
+                let keycode = self.get_key_from_register(register_x)?;
+                if self.keypad.is_down(keycode) {
+                    self.do_noop();
+                }
+            },
+            Instruction::NoopVXNotDown(register_x) => {
+                let keycode = self.get_key_from_register(register_x)?;
+                if !self.keypad.is_down(keycode) {
+                    self.do_noop();
+                }
+            },
+            Instruction::JumpWithOffset { register_x, address } => {
+                let register_offset = if self.quirks.offset_jump {
+                    self.get_register(register_x)
+                } else {
+                    self.get_register(0x00)
+                };
+                self.pc = address + (register_offset as u16);
+            },
+            Instruction::GetKey(register_x) => {
+                match self.keypad.get_first_pressed_key() {
+                    Some(keycode) => {
+                        self.set_register(register_x, (keycode as u32) as u8);
+                    },
+                    None => {
+                        self.pc = self.pc.wrapping_sub(2);
+                    }
+                }
+
+            },
+            Instruction::Random { register_x, mask } => {
+                let random_number = self.rng.gen::<u8>();
+                self.set_register(register_x, random_number & mask);
+            },
+            Instruction::StoreFlags(inclusive_end_register_x) => {
+                for i in 0..=inclusive_end_register_x {
+                    self.rpl_flags[i as usize] = self.get_register(i);
+                }
+            },
+            Instruction::LoadFlags(inclusive_end_register_x) => {
+                for i in 0..=inclusive_end_register_x {
+                    let value = self.rpl_flags[i as usize];
+                    self.set_register(i, value);
+                }
+            },
+            Instruction::SelectPlanes(planes) => self.vram.select_planes(planes),
+        }
+        Ok(())
+    }
+
+    pub fn tick_timers(& mut self) {
+        self.waiting_for_vblank = false;
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+    }
+
+    pub fn should_play_sound(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    pub fn audio_state(&self) -> AudioState {
+        AudioState {
+            playing: self.should_play_sound(),
+            pattern: self.audio_pattern,
+            pitch: self.pitch,
+        }
+    }
+
+    pub fn set_register(& mut self, register: u8, value: u8) {
+        self.registers[register as usize] = value;
+    }
+
+    fn get_register(& mut self, register: u8) -> u8 {
+        self.registers[register as usize]
+    }
+
+    fn draw_sprite(& mut self, register_x: u8, register_y: u8, n: u8) {
+        let width = self.vram.width as u16;
+        let height = self.vram.height as u16;
+        // DXY0 draws a 16x16 sprite made of 2 bytes per row
+        let (sprite_width, rows) = if n == 0 { (16, 16) } else { (8, n as u16) };
+        let bytes_per_row = sprite_width / 8;
+        // The starting position always wraps, the sprite itself wraps or clips depending on the quirk
+        let start_x = self.get_register(register_x) as u16 % width;
+        let start_y = self.get_register(register_y) as u16 % height;
+        // Rows that turned off a pixel in any plane, or were clipped at the bottom edge
+        let mut collided_rows = vec![false; rows as usize];
+        let mut clipped_rows = vec![false; rows as usize];
+
+        // Each selected plane consumes its own sprite, one after another from I
+        let mut row_address = self.index_register;
+        for plane in 0..PLANE_COUNT {
+            if self.vram.selected_planes() & (1 << plane) == 0 {
+                continue;
+            }
+            for row in 0..rows {
+                // Get the Nth row of sprite data, counting from the memory address in the I register
+                let mut sprite_data: u16 = 0;
+                for _ in 0..bytes_per_row {
+                    sprite_data = (sprite_data << 8) | self.memory.get(row_address) as u16;
+                    row_address = row_address.wrapping_add(1);
+                }
+
+                let mut y = start_y + row;
+                if y >= height {
+                    if !self.quirks.sprite_wrap {
+                        clipped_rows[row as usize] = true;
+                        continue;
+                    }
+                    y %= height;
+                }
+                // For each of the pixels/bits in this sprite row (from most signifant to least significant)
+                for column in 0..sprite_width {
+                    let sprite_bit_enabled = sprite_data & (1 << (sprite_width - 1 - column)) != 0;
+                    if !sprite_bit_enabled {
+                        continue;
+                    }
+                    let mut x = start_x + column;
+                    if x >= width {
+                        if !self.quirks.sprite_wrap {
+                            break;
+                        }
+                        x %= width;
+                    }
+                    // Sprite pixels are XORed onto the screen, turning off a set pixel is a collision
+                    let is_set = self.vram.get_plane_cell(plane, x as u8, y as u8);
+                    self.vram.set_plane_cell(plane, x as u8, y as u8, !is_set);
+                    if is_set {
+                        collided_rows[row as usize] = true;
+                    }
+                }
+            }
+        }
+
+        let flag = if self.quirks.count_collided_rows && self.vram.is_hires() {
+            collided_rows
+                .iter()
+                .zip(clipped_rows.iter())
+                .filter(|(collided, clipped)| **collided || **clipped)
+                .count() as u8
+        } else if collided_rows.contains(&true) {
+            1
+        } else {
+            0
+        };
+        self.set_register(0x0F, flag);
+    }
+
+    /// Writes the result of an 8XYN instruction to VX, then its flag (if any) to VF. Like the
+    /// COSMAC VIP and the test suites that check flags, the operands are read before anything is
+    /// written and the flag is written last, so when X is F the flag replaces the result, and when
+    /// Y is F the operand is VF as it was before the instruction.
+    fn set_result_and_flag(& mut self, register_x: u8, result: u8, flag: Option<u8>) {
+        self.set_register(register_x, result);
+        if let Some(flag) = flag {
+            self.set_register(0x0F, flag);
+        }
+    }
+
+    /// The value 8XY6/8XYE shift: VY with the copy shift quirk, otherwise VX
+    fn get_shift_source(& mut self, register_x: u8, register_y: u8) -> u8 {
+        if self.quirks.copy_shift {
+            self.get_register(register_y)
+        } else {
+            self.get_register(register_x)
+        }
+    }
+
+    fn increment_index_after_memory_access(& mut self, register_x: u8) {
+        let amount = self.quirks.memory_increment.amount(register_x);
+        self.index_register = self.index_register.wrapping_add(amount);
+    }
+
+    fn do_noop(& mut self) {
+        // The XO-CHIP F000 NNNN instruction is 4 bytes long and must be skipped as a whole
+        let skipped_length = if self.memory.get_word(self.pc) == 0xF000 { 4 } else { 2 };
+        self.pc = self.pc.wrapping_add(skipped_length);
+    }
+
+    fn get_key_from_register(& mut self, register: u8) -> Result<Keycode, DecodeError> {
+        let value = self.get_register(register);
+        Keypad::from_raw(value as u32).ok_or_else(|| self.error(DecodeErrorKind::InvalidKey(value)))
+    }
+
+    fn error(&self, kind: DecodeErrorKind) -> DecodeError {
+        DecodeError::new(kind, self.opcode, self.instruction_pc)
+    }
+
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.pc);
+        writer.write_u16(self.instruction_pc);
+        writer.write_u16(self.opcode);
+        writer.write_bytes(&self.registers);
+        writer.write_u16(self.index_register);
+        writer.write_u16(self.call_stack.len() as u16);
+        for address in &self.call_stack {
+            writer.write_u16(*address);
+        }
+        savestate::write_quirks(writer, &self.quirks);
+        writer.write_u8(self.sound_timer);
+        writer.write_u8(self.delay_timer);
+        writer.write_bytes(&self.rpl_flags);
+        writer.write_bool(self.exited);
+        writer.write_bool(self.audio_pattern.is_some());
+        writer.write_bytes(&self.audio_pattern.unwrap_or_default());
+        writer.write_u8(self.pitch);
+        writer.write_bool(self.waiting_for_vblank);
+        writer.write_u64(self.seed);
+        writer.write_u128(self.rng.get_word_pos());
+        self.memory.save_state(writer);
+        self.vram.save_state(writer);
+        self.keypad.save_state(writer);
+    }
+
+    pub(crate) fn load_state(reader: &mut StateReader) -> Result<Cpu, SavestateError> {
+        let pc = reader.read_u16()?;
+        let instruction_pc = reader.read_u16()?;
+        let opcode = reader.read_u16()?;
+        let registers = reader.read_bytes()?.to_vec();
+        if registers.len() != 16 {
+            return Err(SavestateError::Invalid(format!("{} registers", registers.len())));
+        }
+        let index_register = reader.read_u16()?;
+        let call_stack_length = reader.read_u16()?;
+        if call_stack_length as usize > CALL_STACK_SIZE {
+            return Err(SavestateError::Invalid(format!("{} nested calls", call_stack_length)));
+        }
+        let mut call_stack = vec![];
+        for _ in 0..call_stack_length {
+            call_stack.push(reader.read_u16()?);
+        }
+        let quirks = savestate::read_quirks(reader)?;
+        let sound_timer = reader.read_u8()?;
+        let delay_timer = reader.read_u8()?;
+        let rpl_flags = reader.read_bytes()?.to_vec();
+        if rpl_flags.len() != 16 {
+            return Err(SavestateError::Invalid(format!("{} RPL flags", rpl_flags.len())));
+        }
+        let exited = reader.read_bool()?;
+        let has_audio_pattern = reader.read_bool()?;
+        let audio_pattern: [u8; 16] = reader
+            .read_bytes()?
+            .try_into()
+            .map_err(|_| SavestateError::Invalid("Audio pattern is not 16 bytes".to_string()))?;
+        let pitch = reader.read_u8()?;
+        let waiting_for_vblank = reader.read_bool()?;
+        let seed = reader.read_u64()?;
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        rng.set_word_pos(reader.read_u128()?);
+        let memory = Memory::load_state(reader)?;
+        let vram = VRAM::load_state(reader)?;
+        let keypad = Keypad::load_state(reader)?;
+        Ok(Cpu {
+            pc,
+            instruction_pc,
+            opcode,
+            memory,
+            registers,
+            index_register,
+            vram,
+            call_stack,
+            quirks,
+            sound_timer,
+            delay_timer,
+            keypad,
+            rpl_flags,
+            exited,
+            audio_pattern: if has_audio_pattern { Some(audio_pattern) } else { None },
+            pitch,
+            waiting_for_vblank,
+            seed,
+            rng,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::get_digits;
+    use crate::cpu::AudioState;
+    use crate::cpu::CALL_STACK_SIZE;
+    use crate::cpu::Cpu;
+    use crate::cpu::Memory;
+    use crate::error::DecodeErrorKind;
+    use crate::quirks::MemoryIncrement;
+    use crate::quirks::Platform;
+    use crate::quirks::Quirks;
+
+    fn run(cpu: &mut Cpu, instructions: usize) {
+        for _ in 0..instructions {
+            cpu.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_get_digits() {
+        assert_eq!(get_digits(156), [1, 5, 6]);
+        assert_eq!(get_digits(150), [1, 5, 0]);
+        assert_eq!(get_digits(42), [0, 4, 2]);
+        assert_eq!(get_digits(0), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_bcd_all_values() {
+        for value in 0..=255u8 {
+            let rom = vec![
+                0x60, value, // v0 := value
+                0xA3, 0x00, // i := 0x300
+                0xF0, 0x33, // bcd v0
+            ];
+            let mut cpu = Cpu::new(Memory::new(rom).unwrap(), Quirks::default());
+            // Stale data that every digit must overwrite
+            for address in 0x300..0x303 {
+                cpu.memory.set(address, 0xFF);
+            }
+            run(&mut cpu, 3);
+            let digits = [value / 100, (value % 100) / 10, value % 10];
+            assert_eq!(cpu.memory().data()[0x300..0x303], digits, "BCD of {}", value);
+            assert_eq!(cpu.memory().get(0x303), 0, "BCD of {}", value);
+            assert_eq!(cpu.index_register(), 0x300);
+        }
+    }
+
+    #[test]
+    fn test_decode_unknown_instructions() {
+        let cases = [
+            (0x0123, DecodeErrorKind::UnknownInstruction),
+            (0x5AB4, DecodeErrorKind::UnknownInstruction),
+            (0x8AB8, DecodeErrorKind::UnknownArithmeticInstruction),
+            (0xE1FF, DecodeErrorKind::UnknownEPrefixInstruction),
+            (0xF1FF, DecodeErrorKind::UnknownFPrefixInstruction),
+        ];
+        for (opcode, kind) in cases {
+            let error = Cpu::decode_at(opcode, 0x0234).unwrap_err();
+            assert_eq!(error.kind, kind);
+            assert_eq!(error.opcode, opcode);
+            assert_eq!(error.pc, 0x0234);
+        }
+    }
+
+    #[test]
+    fn test_skip_long_index_load() {
+        let rom = vec![
+            0x30, 0x00, // skip next if V0 == 0
+            0xF0, 0x00, 0x12, 0x34, // i := long 0x1234
+            0xF0, 0x00, 0xAB, 0xCD, // i := long 0xABCD
+        ];
+        let mut cpu = Cpu::new(Memory::new(rom).unwrap(), Quirks::default());
+        run(&mut cpu, 2);
+        assert_eq!(cpu.index_register(), 0xABCD);
+        assert_eq!(cpu.pc(), 0x20A);
+    }
+
+    #[test]
+    fn test_register_ranges() {
+        let rom = vec![
+            0x61, 0x11, // v1 := 0x11
+            0x62, 0x22, // v2 := 0x22
+            0x63, 0x33, // v3 := 0x33
+            0xA3, 0x00, // i := 0x300
+            0x53, 0x12, // save v3 - v1
+            0x54, 0x63, // load v4 - v6
+        ];
+        let mut cpu = Cpu::new(Memory::new(rom).unwrap(), Quirks::default());
+        run(&mut cpu, 6);
+        assert_eq!(cpu.registers()[4..=6], [0x33, 0x22, 0x11]);
+        assert_eq!(cpu.index_register(), 0x300);
+    }
+
+    #[test]
+    fn test_audio_pattern_and_pitch() {
+        let rom = vec![
+            0xA2, 0x08, // i := pattern
+            0xF0, 0x02, // audio
+            0x60, 0x70, // v0 := 112
+            0xF0, 0x3A, // pitch := v0
+            0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, // pattern
+            0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA,
+        ];
+        let mut cpu = Cpu::new(Memory::new(rom).unwrap(), Quirks::default());
+        assert_eq!(cpu.audio_state().pattern, None);
+        assert_eq!(cpu.audio_state().playback_rate(), 4000.0);
+        run(&mut cpu, 4);
+        let state: AudioState = cpu.audio_state();
+        assert_eq!(state.pattern.unwrap()[0..2], [0xFF, 0x00]);
+        assert_eq!(state.pattern.unwrap()[15], 0xAA);
+        assert_eq!(state.playback_rate(), 8000.0);
+    }
+
+    #[test]
+    fn test_memory_increment() {
+        let cases = [
+            (MemoryIncrement::XPlusOne, 0x304),
+            (MemoryIncrement::X, 0x302),
+            (MemoryIncrement::None, 0x300),
+        ];
+        for (memory_increment, expected_index) in cases {
+            let rom = vec![
+                0xA3, 0x00, // i := 0x300
+                0xF2, 0x55, // save v2
+                0xF0, 0x65, // load v0
+            ];
+            let quirks = Quirks { memory_increment, ..Quirks::default() };
+            let mut cpu = Cpu::new(Memory::new(rom).unwrap(), quirks);
+            run(&mut cpu, 3);
+            assert_eq!(cpu.index_register(), expected_index);
+        }
+    }
+
+    #[test]
+    fn test_vf_reset() {
+        for (vf_reset, expected_flag) in [(true, 0), (false, 0xAA)] {
+            for logical_operation in [0x11, 0x12, 0x13] {
+                let rom = vec![
+                    0x6F, 0xAA, // vf := 0xAA
+                    0x81, logical_operation, // v1 |= v1, v1 &= v1, v1 ^= v1
+                ];
+                let quirks = Quirks { vf_reset, ..Quirks::default() };
+                let mut cpu = Cpu::new(Memory::new(rom).unwrap(), quirks);
+                run(&mut cpu, 2);
+                assert_eq!(cpu.registers()[0x0F], expected_flag);
+            }
+        }
+    }
+
+    fn draw_cpu(quirks: Quirks, setup: &[u8]) -> Cpu {
+        // Point I at the sprite following the setup instructions
+        let sprite_address = 0x200 + 2 + setup.len() as u16;
+        let mut rom = vec![0xA0 | (sprite_address >> 8) as u8, sprite_address as u8];
+        rom.extend_from_slice(setup);
+        // A 3 row sprite with the leftmost and rightmost pixel set
+        rom.extend_from_slice(&[0x81, 0x81, 0x81]);
+        Cpu::new(Memory::new(rom).unwrap(), quirks)
+    }
+
+    #[test]
+    fn test_sprite_clip_and_wrap() {
+        let setup = [
+            0x60, 0x3C, // v0 := 60
+            0x61, 0x1F, // v1 := 31
+            0xD0, 0x13, // sprite v0 v1 3
+        ];
+        let mut clipping = draw_cpu(Quirks::default(), &setup);
+        run(&mut clipping, 4);
+        assert!(clipping.vram().get_cell(60, 31));
+        assert!(!clipping.vram().get_cell(3, 31));
+        assert!(!clipping.vram().get_cell(60, 0));
+
+        let quirks = Quirks { sprite_wrap: true, ..Quirks::default() };
+        let mut wrapping = draw_cpu(quirks, &setup);
+        run(&mut wrapping, 4);
+        assert!(wrapping.vram().get_cell(60, 31));
+        assert!(wrapping.vram().get_cell(3, 31));
+        assert!(wrapping.vram().get_cell(60, 0));
+        assert!(wrapping.vram().get_cell(3, 1));
+    }
+
+    #[test]
+    fn test_sprite_collision() {
+        for sprite_wrap in [false, true] {
+            let setup = [
+                0x60, 0x3C, // v0 := 60
+                0x61, 0x1F, // v1 := 31
+            ];
+            let quirks = Quirks { sprite_wrap, ..Quirks::default() };
+            let mut cpu = draw_cpu(quirks, &setup);
+            // Draw the sprite twice, the second time erases it
+            let draw = [0xD0, 0x13, 0xD0, 0x13];
+            for (i, byte) in draw.iter().enumerate() {
+                cpu.memory.set(0x300 + i as u16, *byte);
+            }
+            run(&mut cpu, 3);
+            cpu.pc = 0x300;
+            run(&mut cpu, 1);
+            assert_eq!(cpu.registers()[0x0F], 0);
+            run(&mut cpu, 1);
+            assert_eq!(cpu.registers()[0x0F], 1);
+            assert!(!cpu.vram().get_cell(60, 31));
+        }
+    }
+
+    #[test]
+    fn test_sprite_collided_rows() {
+        let setup = [
+            0x00, 0xFF, // hires
+            0x60, 0x10, // v0 := 16
+            0x61, 0x3E, // v1 := 62
+        ];
+        let quirks = Quirks { count_collided_rows: true, ..Quirks::default() };
+        let mut cpu = draw_cpu(quirks, &setup);
+        let draw = [0xD0, 0x13, 0xD0, 0x13];
+        for (i, byte) in draw.iter().enumerate() {
+            cpu.memory.set(0x300 + i as u16, *byte);
+        }
+        run(&mut cpu, 4);
+        cpu.pc = 0x300;
+        run(&mut cpu, 1);
+        // The third row is clipped at the bottom edge
+        assert_eq!(cpu.registers()[0x0F], 1);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers()[0x0F], 3);
+    }
+
+    #[test]
+    fn test_display_wait() {
+        for display_wait in [false, true] {
+            let quirks = Quirks { display_wait, ..Quirks::default() };
+            let mut cpu = draw_cpu(quirks, &[0xD0, 0x13]);
+            run(&mut cpu, 2);
+            assert_eq!(cpu.is_waiting_for_vblank(), display_wait);
+            cpu.tick_timers();
+            assert!(!cpu.is_waiting_for_vblank());
+        }
+    }
+
+    #[test]
+    fn test_seeded_random() {
+        let rom = vec![
+            0xC0, 0xFF, // v0 := random 0xFF
+            0xC1, 0xFF, // v1 := random 0xFF
+            0xC2, 0x0F, // v2 := random 0x0F
+        ];
+        let mut first = Cpu::with_seed(Memory::new(rom.clone()).unwrap(), Quirks::default(), 42);
+        let mut second = Cpu::with_seed(Memory::new(rom).unwrap(), Quirks::default(), 42);
+        run(&mut first, 3);
+        run(&mut second, 3);
+        assert_eq!(first.registers(), second.registers());
+        assert!(first.registers()[2] <= 0x0F);
+    }
+
+    /// State after running an opcode case. Registers, memory and pixels that are not listed are
+    /// not checked.
+    #[derive(Default)]
+    struct Expected {
+        pc: u16,
+        registers: Vec<(u8, u8)>,
+        index_register: Option<u16>,
+        memory: Vec<(u16, u8)>,
+        // Pixels that must be set or unset
+        pixels: Vec<(u8, u8, bool)>,
+        call_stack: Vec<u16>,
+        sound_timer: Option<u8>,
+        width: Option<u8>,
+        selected_planes: Option<u8>,
+    }
+
+    struct OpcodeCase {
+        name: &'static str,
+        rom: Vec<u8>,
+        // Keys held down while running
+        keys: u16,
+        steps: usize,
+        expected: fn(Quirks) -> Expected,
+    }
+
+    fn case(name: &'static str, rom: Vec<u8>, steps: usize, expected: fn(Quirks) -> Expected) -> OpcodeCase {
+        OpcodeCase { name, rom, keys: 0, steps, expected }
+    }
+
+    /// The defaults, every platform and every quirk changed on its own.
+    fn quirk_settings() -> Vec<Quirks> {
+        let default = Quirks::default();
+        let mut settings = vec![default];
+        settings.extend(Platform::ALL.iter().map(|platform| platform.quirks()));
+        settings.extend([
+            Quirks { copy_shift: !default.copy_shift, ..default },
+            Quirks { offset_jump: !default.offset_jump, ..default },
+            Quirks { memory_increment: MemoryIncrement::XPlusOne, ..default },
+            Quirks { memory_increment: MemoryIncrement::X, ..default },
+            Quirks { memory_increment: MemoryIncrement::None, ..default },
+            Quirks { vf_reset: !default.vf_reset, ..default },
+            Quirks { sprite_wrap: !default.sprite_wrap, ..default },
+            Quirks { count_collided_rows: !default.count_collided_rows, ..default },
+            Quirks { display_wait: !default.display_wait, ..default },
+        ]);
+        settings
+    }
+
+    fn opcode_cases() -> Vec<OpcodeCase> {
+        vec![
+            case("00E0 clear", vec![
+                0xF0, 0x29, // i := hex v0
+                0xD0, 0x05, // sprite v0 v0 5
+                0x00, 0xE0, // clear
+            ], 3, |_| Expected { pc: 0x206, pixels: vec![(0, 0, false)], ..Expected::default() }),
+            case("00CN scroll down", vec![
+                0xF0, 0x29, // i := hex v0
+                0xD0, 0x05, // sprite v0 v0 5
+                0x00, 0xC2, // scroll-down 2
+            ], 3, |_| Expected { pc: 0x206, pixels: vec![(0, 0, false), (0, 2, true), (0, 6, true)], ..Expected::default() }),
+            case("00DN scroll up", vec![
+                0x61, 0x04, // v1 := 4
+                0xF0, 0x29, // i := hex v0
+                0xD0, 0x15, // sprite v0 v1 5
+                0x00, 0xD3, // scroll-up 3
+            ], 4, |_| Expected { pc: 0x208, pixels: vec![(0, 1, true), (0, 5, true), (0, 6, false)], ..Expected::default() }),
+            case("00FB scroll right", vec![
+                0xF0, 0x29, // i := hex v0
+                0xD0, 0x05, // sprite v0 v0 5
+                0x00, 0xFB, // scroll-right
+            ], 3, |_| Expected { pc: 0x206, pixels: vec![(0, 0, false), (4, 0, true)], ..Expected::default() }),
+            case("00FC scroll left", vec![
+                0x61, 0x04, // v1 := 4
+                0xF0, 0x29, // i := hex v0
+                0xD1, 0x05, // sprite v1 v0 5
+                0x00, 0xFC, // scroll-left
+            ], 4, |_| Expected { pc: 0x208, pixels: vec![(0, 0, true), (4, 0, false)], ..Expected::default() }),
+            case("00FD exit", vec![
+                0x00, 0xFD, // exit
+            ], 2, |_| Expected { pc: 0x200, ..Expected::default() }),
+            case("00FF hires", vec![
+                0x00, 0xFF, // hires
+            ], 1, |_| Expected { pc: 0x202, width: Some(128), ..Expected::default() }),
+            case("00FE lores", vec![
+                0x00, 0xFF, // hires
+                0x00, 0xFE, // lores
+            ], 2, |_| Expected { pc: 0x204, width: Some(64), ..Expected::default() }),
+            case("1NNN jump", vec![
+                0x12, 0x34, // jump 0x234
+            ], 1, |_| Expected { pc: 0x234, ..Expected::default() }),
+            case("2NNN call", vec![
+                0x22, 0x06, // :call 0x206
+            ], 1, |_| Expected { pc: 0x206, call_stack: vec![0x202], ..Expected::default() }),
+            case("00EE return", vec![
+                0x22, 0x02, // :call 0x202
+                0x00, 0xEE, // return
+            ], 2, |_| Expected { pc: 0x202, ..Expected::default() }),
+            case("3XNN skip taken", vec![
+                0x60, 0x42, // v0 := 0x42
+                0x30, 0x42, // if v0 != 0x42 then
+                0x60, 0x01, // v0 := 1
+                0x61, 0x02, // v1 := 2
+            ], 3, |_| Expected { pc: 0x208, registers: vec![(0, 0x42), (1, 0x02)], ..Expected::default() }),
+            case("3XNN skip not taken", vec![
+                0x60, 0x42, // v0 := 0x42
+                0x30, 0x43, // if v0 != 0x43 then
+                0x60, 0x01, // v0 := 1
+            ], 3, |_| Expected { pc: 0x206, registers: vec![(0, 0x01)], ..Expected::default() }),
+            case("4XNN skip taken", vec![
+                0x40, 0x01, // if v0 == 1 then
+                0x60, 0x01, // v0 := 1
+            ], 1, |_| Expected { pc: 0x204, registers: vec![(0, 0x00)], ..Expected::default() }),
+            case("4XNN skip not taken", vec![
+                0x40, 0x00, // if v0 == 0 then
+                0x60, 0x01, // v0 := 1
+            ], 2, |_| Expected { pc: 0x204, registers: vec![(0, 0x01)], ..Expected::default() }),
+            case("5XY0 skip taken", vec![
+                0x60, 0x07, // v0 := 7
+                0x61, 0x07, // v1 := 7
+                0x50, 0x10, // if v0 != v1 then
+            ], 3, |_| Expected { pc: 0x208, ..Expected::default() }),
+            case("5XY0 skip not taken", vec![
+                0x60, 0x07, // v0 := 7
+                0x50, 0x10, // if v0 != v1 then
+            ], 2, |_| Expected { pc: 0x204, ..Expected::default() }),
+            case("9XY0 skip taken", vec![
+                0x60, 0x07, // v0 := 7
+                0x90, 0x10, // if v0 == v1 then
+            ], 2, |_| Expected { pc: 0x206, ..Expected::default() }),
+            case("9XY0 skip not taken", vec![
+                0x90, 0x10, // if v0 == v1 then
+            ], 1, |_| Expected { pc: 0x202, ..Expected::default() }),
+            case("6XNN set", vec![
+                0x6A, 0xBC, // va := 0xBC
+            ], 1, |_| Expected { pc: 0x202, registers: vec![(0xA, 0xBC)], ..Expected::default() }),
+            case("7XNN add wraps without carry", vec![
+                0x6F, 0x05, // vf := 5
+                0x60, 0xFF, // v0 := 0xFF
+                0x70, 0x02, // v0 += 2
+            ], 3, |_| Expected { pc: 0x206, registers: vec![(0, 0x01), (0xF, 0x05)], ..Expected::default() }),
+            case("8XY0 set", vec![
+                0x61, 0x33, // v1 := 0x33
+                0x80, 0x10, // v0 := v1
+            ], 2, |_| Expected { pc: 0x204, registers: vec![(0, 0x33), (1, 0x33)], ..Expected::default() }),
+            case("8XY1 or", vec![
+                0x6F, 0x05, // vf := 5
+                0x60, 0x0C, // v0 := 0x0C
+                0x61, 0x0A, // v1 := 0x0A
+                0x80, 0x11, // v0 |= v1
+            ], 4, |q| Expected { pc: 0x208, registers: vec![(0, 0x0E), (0xF, if q.vf_reset { 0 } else { 5 })], ..Expected::default() }),
+            case("8XY2 and", vec![
+                0x6F, 0x05, // vf := 5
+                0x60, 0x0C, // v0 := 0x0C
+                0x61, 0x0A, // v1 := 0x0A
+                0x80, 0x12, // v0 &= v1
+            ], 4, |q| Expected { pc: 0x208, registers: vec![(0, 0x08), (0xF, if q.vf_reset { 0 } else { 5 })], ..Expected::default() }),
+            case("8XY3 xor", vec![
+                0x6F, 0x05, // vf := 5
+                0x60, 0x0C, // v0 := 0x0C
+                0x61, 0x0A, // v1 := 0x0A
+                0x80, 0x13, // v0 ^= v1
+            ], 4, |q| Expected { pc: 0x208, registers: vec![(0, 0x06), (0xF, if q.vf_reset { 0 } else { 5 })], ..Expected::default() }),
+            case("8XY4 add without carry", vec![
+                0x6F, 0x05, // vf := 5
+                0x60, 0x10, // v0 := 0x10
+                0x61, 0xEF, // v1 := 0xEF
+                0x80, 0x14, // v0 += v1
+            ], 4, |_| Expected { pc: 0x208, registers: vec![(0, 0xFF), (0xF, 0)], ..Expected::default() }),
+            case("8XY4 add with carry", vec![
+                0x60, 0xFF, // v0 := 0xFF
+                0x61, 0x01, // v1 := 1
+                0x80, 0x14, // v0 += v1
+            ], 3, |_| Expected { pc: 0x206, registers: vec![(0, 0x00), (0xF, 1)], ..Expected::default() }),
+            case("8XY4 add largest values", vec![
+                0x60, 0xFF, // v0 := 0xFF
+                0x80, 0x04, // v0 += v0
+            ], 2, |_| Expected { pc: 0x204, registers: vec![(0, 0xFE), (0xF, 1)], ..Expected::default() }),
+            case("8XY5 subtract without borrow", vec![
+                0x60, 0x03, // v0 := 3
+                0x61, 0x01, // v1 := 1
+                0x80, 0x15, // v0 -= v1
+            ], 3, |_| Expected { pc: 0x206, registers: vec![(0, 0x02), (0xF, 1)], ..Expected::default() }),
+            case("8XY5 subtract equal values", vec![
+                0x60, 0x05, // v0 := 5
+                0x61, 0x05, // v1 := 5
+                0x80, 0x15, // v0 -= v1
+            ], 3, |_| Expected { pc: 0x206, registers: vec![(0, 0x00), (0xF, 1)], ..Expected::default() }),
+            case("8XY5 subtract with borrow", vec![
+                0x60, 0x01, // v0 := 1
+                0x61, 0x02, // v1 := 2
+                0x80, 0x15, // v0 -= v1
+            ], 3, |_| Expected { pc: 0x206, registers: vec![(0, 0xFF), (0xF, 0)], ..Expected::default() }),
+            case("8XY7 reverse subtract without borrow", vec![
+                0x60, 0x01, // v0 := 1
+                0x61, 0x03, // v1 := 3
+                0x80, 0x17, // v0 =- v1
+            ], 3, |_| Expected { pc: 0x206, registers: vec![(0, 0x02), (0xF, 1)], ..Expected::default() }),
+            case("8XY7 reverse subtract equal values", vec![
+                0x60, 0x05, // v0 := 5
+                0x61, 0x05, // v1 := 5
+                0x80, 0x17, // v0 =- v1
+            ], 3, |_| Expected { pc: 0x206, registers: vec![(0, 0x00), (0xF, 1)], ..Expected::default() }),
+            case("8XY7 reverse subtract with borrow", vec![
+                0x60, 0x02, // v0 := 2
+                0x61, 0x01, // v1 := 1
+                0x80, 0x17, // v0 =- v1
+            ], 3, |_| Expected { pc: 0x206, registers: vec![(0, 0xFF), (0xF, 0)], ..Expected::default() }),
+            case("8XY6 shift right", vec![
+                0x60, 0x05, // v0 := 5
+                0x61, 0x02, // v1 := 2
+                0x80, 0x16, // v0 >>= v1
+            ], 3, |q| Expected {
+                pc: 0x206,
+                registers: if q.copy_shift { vec![(0, 0x01), (1, 0x02), (0xF, 0)] } else { vec![(0, 0x02), (1, 0x02), (0xF, 1)] },
+                ..Expected::default()
+            }),
+            case("8XYE shift left", vec![
+                0x60, 0x81, // v0 := 0x81
+                0x61, 0x40, // v1 := 0x40
+                0x80, 0x1E, // v0 <<= v1
+            ], 3, |q| Expected {
+                pc: 0x206,
+                registers: if q.copy_shift { vec![(0, 0x80), (1, 0x40), (0xF, 0)] } else { vec![(0, 0x02), (1, 0x40), (0xF, 1)] },
+                ..Expected::default()
+            }),
+            case("ANNN set index", vec![
+                0xA1, 0x23, // i := 0x123
+            ], 1, |_| Expected { pc: 0x202, index_register: Some(0x123), ..Expected::default() }),
+            case("F000 NNNN set long index", vec![
+                0xF0, 0x00, 0xBE, 0xEF, // i := long 0xBEEF
+            ], 1, |_| Expected { pc: 0x204, index_register: Some(0xBEEF), ..Expected::default() }),
+            case("BNNN jump with offset", vec![
+                0x60, 0x02, // v0 := 2
+                0x63, 0x04, // v3 := 4
+                0xB3, 0x04, // jump0 0x304
+            ], 3, |q| Expected { pc: if q.offset_jump { 0x308 } else { 0x306 }, ..Expected::default() }),
+            case("CXNN random with empty mask", vec![
+                0x60, 0xFF, // v0 := 0xFF
+                0xC0, 0x00, // v0 := random 0
+            ], 2, |_| Expected { pc: 0x204, registers: vec![(0, 0x00)], ..Expected::default() }),
+            case("DXYN draw", vec![
+                0xF0, 0x29, // i := hex v0
+                0xD0, 0x05, // sprite v0 v0 5
+            ], 2, |_| Expected {
+                pc: 0x204,
+                registers: vec![(0xF, 0)],
+                pixels: vec![(0, 0, true), (3, 0, true), (4, 0, false), (1, 1, false), (0, 4, true)],
+                ..Expected::default()
+            }),
+            case("DXYN erase", vec![
+                0xF0, 0x29, // i := hex v0
+                0xD0, 0x05, // sprite v0 v0 5
+                0xD0, 0x05, // sprite v0 v0 5
+            ], 3, |_| Expected { pc: 0x206, registers: vec![(0xF, 1)], pixels: vec![(0, 0, false)], ..Expected::default() }),
+            case("DXYN draw across the edge", vec![
+                0x61, 0x3E, // v1 := 62
+                0xF0, 0x29, // i := hex v0
+                0xD1, 0x05, // sprite v1 v0 5
+            ], 3, |q| Expected {
+                pc: 0x206,
+                pixels: vec![(62, 0, true), (63, 0, true), (0, 0, q.sprite_wrap), (1, 0, q.sprite_wrap)],
+                ..Expected::default()
+            }),
+            case("FX07 and FX15 delay timer", vec![
+                0x60, 0x20, // v0 := 0x20
+                0xF0, 0x15, // delay := v0
+                0xF1, 0x07, // v1 := delay
+            ], 3, |_| Expected { pc: 0x206, registers: vec![(1, 0x20)], ..Expected::default() }),
+            case("FX18 sound timer", vec![
+                0x60, 0x20, // v0 := 0x20
+                0xF0, 0x18, // buzzer := v0
+            ], 2, |_| Expected { pc: 0x204, sound_timer: Some(0x20), ..Expected::default() }),
+            case("FX1E add to index", vec![
+                0x6F, 0x05, // vf := 5
+                0x60, 0x02, // v0 := 2
+                0xA3, 0x00, // i := 0x300
+                0xF0, 0x1E, // i += v0
+            ], 4, |_| Expected { pc: 0x208, index_register: Some(0x302), registers: vec![(0xF, 5)], ..Expected::default() }),
+            case("FX1E add to index past 0xFFF", vec![
+                0x6F, 0x05, // vf := 5
+                0x60, 0x01, // v0 := 1
+                0xAF, 0xFF, // i := 0xFFF
+                0xF0, 0x1E, // i += v0
+            ], 4, |_| Expected { pc: 0x208, index_register: Some(0x1000), registers: vec![(0xF, 5)], ..Expected::default() }),
+            case("FX29 font character", vec![
+                0x60, 0x1A, // v0 := 0x1A
+                0xF0, 0x29, // i := hex v0
+            ], 2, |_| Expected { pc: 0x204, index_register: Some(0x50 + 5 * 0xA), ..Expected::default() }),
+            case("FX30 big font character", vec![
+                0x60, 0x1A, // v0 := 0x1A
+                0xF0, 0x30, // i := bighex v0
+            ], 2, |_| Expected { pc: 0x204, index_register: Some(0xA0 + 10 * 0xA), ..Expected::default() }),
+            case("FX33 BCD", vec![
+                0x60, 0x9C, // v0 := 156
+                0xA3, 0x00, // i := 0x300
+                0xF0, 0x33, // bcd v0
+            ], 3, |_| Expected {
+                pc: 0x206,
+                index_register: Some(0x300),
+                memory: vec![(0x300, 1), (0x301, 5), (0x302, 6)],
+                ..Expected::default()
+            }),
+            case("FX33 BCD of the largest value", vec![
+                0x60, 0xFF, // v0 := 255
+                0xA3, 0x00, // i := 0x300
+                0xF0, 0x33, // bcd v0
+            ], 3, |_| Expected { pc: 0x206, memory: vec![(0x300, 2), (0x301, 5), (0x302, 5)], ..Expected::default() }),
+            case("FX33 BCD with leading zeros", vec![
+                0x60, 0x07, // v0 := 7
+                0xA3, 0x00, // i := 0x300
+                0xF0, 0x33, // bcd v0
+            ], 3, |_| Expected { pc: 0x206, memory: vec![(0x300, 0), (0x301, 0), (0x302, 7)], ..Expected::default() }),
+            case("FX55 save", vec![
+                0x60, 0x11, // v0 := 0x11
+                0x61, 0x22, // v1 := 0x22
+                0x62, 0x33, // v2 := 0x33
+                0xA3, 0x00, // i := 0x300
+                0xF1, 0x55, // save v1
+            ], 5, |q| Expected {
+                pc: 0x20A,
+                index_register: Some(0x300 + q.memory_increment.amount(1)),
+                memory: vec![(0x300, 0x11), (0x301, 0x22), (0x302, 0x00)],
+                ..Expected::default()
+            }),
+            case("FX65 load", vec![
+                0xA2, 0x04, // i := 0x204
+                0xF1, 0x65, // load v1
+                0x12, 0x04, // jump 0x204, also the data loaded
+            ], 2, |q| Expected {
+                pc: 0x204,
+                index_register: Some(0x204 + q.memory_increment.amount(1)),
+                registers: vec![(0, 0x12), (1, 0x04), (2, 0x00)],
+                ..Expected::default()
+            }),
+            case("5XY2 save range", vec![
+                0x61, 0x11, // v1 := 0x11
+                0x62, 0x22, // v2 := 0x22
+                0xA3, 0x00, // i := 0x300
+                0x52, 0x12, // save v2 - v1
+            ], 4, |_| Expected {
+                pc: 0x208,
+                index_register: Some(0x300),
+                memory: vec![(0x300, 0x22), (0x301, 0x11)],
+                ..Expected::default()
+            }),
+            case("5XY3 load range", vec![
+                0xA2, 0x04, // i := 0x204
+                0x51, 0x23, // load v1 - v2
+                0x12, 0x04, // jump 0x204, also the data loaded
+            ], 2, |_| Expected {
+                pc: 0x204,
+                index_register: Some(0x204),
+                registers: vec![(1, 0x12), (2, 0x04)],
+                ..Expected::default()
+            }),
+            case("FX75 and FX85 flags", vec![
+                0x60, 0x11, // v0 := 0x11
+                0x61, 0x22, // v1 := 0x22
+                0xF1, 0x75, // saveflags v1
+                0x60, 0x00, // v0 := 0
+                0x61, 0x00, // v1 := 0
+                0xF0, 0x85, // loadflags v0
+            ], 6, |_| Expected { pc: 0x20C, registers: vec![(0, 0x11), (1, 0x00)], ..Expected::default() }),
+            case("FN01 select planes", vec![
+                0xF3, 0x01, // plane 3
+            ], 1, |_| Expected { pc: 0x202, selected_planes: Some(3), ..Expected::default() }),
+            case("EX9E skip when not pressed", vec![
+                0x60, 0x05, // v0 := 5
+                0xE0, 0x9E, // if v0 -key then
+            ], 2, |_| Expected { pc: 0x204, ..Expected::default() }),
+            case("EXA1 skip when not pressed", vec![
+                0x60, 0x05, // v0 := 5
+                0xE0, 0xA1, // if v0 key then
+            ], 2, |_| Expected { pc: 0x206, ..Expected::default() }),
+            OpcodeCase {
+                keys: 1 << 5,
+                ..case("EX9E skip when pressed", vec![
+                    0x60, 0x05, // v0 := 5
+                    0xE0, 0x9E, // if v0 -key then
+                ], 2, |_| Expected { pc: 0x206, ..Expected::default() })
+            },
+            OpcodeCase {
+                keys: 1 << 5,
+                ..case("EXA1 skip when pressed", vec![
+                    0x60, 0x05, // v0 := 5
+                    0xE0, 0xA1, // if v0 key then
+                ], 2, |_| Expected { pc: 0x204, ..Expected::default() })
+            },
+            case("FX0A wait for key", vec![
+                0xF0, 0x0A, // v0 := key
+            ], 3, |_| Expected { pc: 0x200, ..Expected::default() }),
+            OpcodeCase {
+                keys: 1 << 0xB,
+                ..case("FX0A get key", vec![
+                    0xF0, 0x0A, // v0 := key
+                ], 1, |_| Expected { pc: 0x202, registers: vec![(0, 0xB)], ..Expected::default() })
+            },
+        ]
+    }
+
+    #[test]
+    fn test_opcodes() {
+        for quirks in quirk_settings() {
+            for case in opcode_cases() {
+                let context = format!("{} with {:?}", case.name, quirks);
+                let mut cpu = Cpu::new(Memory::new(case.rom).unwrap(), quirks);
+                cpu.keypad().set_down_mask(case.keys);
+                run(&mut cpu, case.steps);
+
+                let expected = (case.expected)(quirks);
+                assert_eq!(cpu.pc(), expected.pc, "PC after {}", context);
+                for (register, value) in expected.registers {
+                    assert_eq!(cpu.registers()[register as usize], value, "V{:X} after {}", register, context);
+                }
+                if let Some(index_register) = expected.index_register {
+                    assert_eq!(cpu.index_register(), index_register, "I after {}", context);
+                }
+                for (address, value) in expected.memory {
+                    assert_eq!(cpu.memory().get(address), value, "{:#05X} after {}", address, context);
+                }
+                for (x, y, is_set) in expected.pixels {
+                    assert_eq!(cpu.vram().get_cell(x, y), is_set, "Pixel ({}, {}) after {}", x, y, context);
+                }
+                assert_eq!(cpu.call_stack(), expected.call_stack, "Call stack after {}", context);
+                if let Some(sound_timer) = expected.sound_timer {
+                    assert_eq!(cpu.sound_timer(), sound_timer, "Sound timer after {}", context);
+                }
+                if let Some(width) = expected.width {
+                    assert_eq!(cpu.vram().width, width, "Width after {}", context);
+                }
+                if let Some(selected_planes) = expected.selected_planes {
+                    assert_eq!(cpu.vram().selected_planes(), selected_planes, "Planes after {}", context);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_return_with_empty_stack() {
+        let mut cpu = Cpu::new(Memory::new(vec![0x00, 0xEE]).unwrap(), Quirks::default());
+        let error = cpu.step().unwrap_err();
+        assert_eq!(error.kind, DecodeErrorKind::StackUnderflow);
+        assert_eq!(error.pc, 0x200);
+    }
+
+    #[test]
+    fn test_call_with_full_stack() {
+        // Calls itself forever
+        let mut cpu = Cpu::new(Memory::new(vec![0x22, 0x00]).unwrap(), Quirks::default());
+        for _ in 0..CALL_STACK_SIZE {
+            cpu.step().unwrap();
+        }
+        let error = cpu.step().unwrap_err();
+        assert_eq!(error.kind, DecodeErrorKind::StackOverflow);
+        assert_eq!(cpu.call_stack().len(), CALL_STACK_SIZE);
+    }
+
+    #[test]
+    fn test_repeat_instruction_at_end_of_memory() {
+        let mut cpu = Cpu::new(Memory::new(vec![]).unwrap(), Quirks::default());
+        for (opcode, exits) in [(0xF00A, false), (0x00FD, true)] {
+            cpu.set_memory(0xFFFE, (opcode >> 8) as u8);
+            cpu.set_memory(0xFFFF, opcode as u8);
+            cpu.set_pc(0xFFFE);
+            cpu.step().unwrap();
+            assert_eq!(cpu.pc(), 0xFFFE);
+            assert_eq!(cpu.has_exited(), exits);
+        }
+    }
+
+    /// Result and flag of 8XYN computed from its operands, with no flag for logical operations
+    /// without the VF reset quirk
+    fn arithmetic_reference(n: u8, x: u8, y: u8, quirks: Quirks) -> (u8, Option<u8>) {
+        let shifted = if quirks.copy_shift { y } else { x };
+        match n {
+            0x1 => (x | y, quirks.vf_reset.then_some(0)),
+            0x2 => (x & y, quirks.vf_reset.then_some(0)),
+            0x3 => (x ^ y, quirks.vf_reset.then_some(0)),
+            0x4 => (x.wrapping_add(y), Some((x as u16 + y as u16 > 0xFF) as u8)),
+            0x5 => (x.wrapping_sub(y), Some((x >= y) as u8)),
+            0x6 => (shifted >> 1, Some(shifted & 0x01)),
+            0x7 => (y.wrapping_sub(x), Some((y >= x) as u8)),
+            0xE => (shifted << 1, Some(shifted >> 7)),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_flag_register_as_operand() {
+        let values = [(0xF0, 0x20), (0x20, 0xF0), (0x05, 0x05), (0x81, 0x42), (0x00, 0xFF)];
+        for quirks in quirk_settings() {
+            for n in [0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0xE] {
+                for (a, b) in values {
+                    let context = format!("8XY{:X} with {:#04X} and {:#04X} and {:?}", n, a, b, quirks);
+
+                    // X is F: the flag is written last and replaces the result
+                    let rom = vec![
+                        0x6F, a, // vf := a
+                        0x61, b, // v1 := b
+                        0x8F, 0x10 | n, // vf op= v1
+                    ];
+                    let mut cpu = Cpu::new(Memory::new(rom).unwrap(), quirks);
+                    run(&mut cpu, 3);
+                    let (result, flag) = arithmetic_reference(n, a, b, quirks);
+                    assert_eq!(cpu.registers()[0xF], flag.unwrap_or(result), "VF as X, {}", context);
+                    assert_eq!(cpu.registers()[1], b, "VY with VF as X, {}", context);
+
+                    // Y is F: the operand is VF from before the instruction
+                    let rom = vec![
+                        0x60, a, // v0 := a
+                        0x6F, b, // vf := b
+                        0x80, 0xF0 | n, // v0 op= vf
+                    ];
+                    let mut cpu = Cpu::new(Memory::new(rom).unwrap(), quirks);
+                    run(&mut cpu, 3);
+                    assert_eq!(cpu.registers()[0], result, "VX with VF as Y, {}", context);
+                    assert_eq!(cpu.registers()[0xF], flag.unwrap_or(b), "VF as Y, {}", context);
+                }
+
+                // X and Y are both F
+                let rom = vec![
+                    0x6F, 0x81, // vf := 0x81
+                    0x8F, 0xF0 | n, // vf op= vf
+                ];
+                let mut cpu = Cpu::new(Memory::new(rom).unwrap(), quirks);
+                run(&mut cpu, 2);
+                let (result, flag) = arithmetic_reference(n, 0x81, 0x81, quirks);
+                assert_eq!(cpu.registers()[0xF], flag.unwrap_or(result), "8FF{:X} with {:?}", n, quirks);
+            }
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::cpu::Cpu;
+use crate::error::DecodeError;
+use crate::trace::Tracer;
+
+/// A value that pauses execution whenever an instruction changes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Watchpoint {
+    Register(u8),
+    Index,
+    Memory(u16),
+}
+
+impl Watchpoint {
+    fn value(&self, cpu: &Cpu) -> u16 {
+        match self {
+            Watchpoint::Register(register) => cpu.registers()[*register as usize] as u16,
+            Watchpoint::Index => cpu.index_register(),
+            Watchpoint::Memory(address) => cpu.memory().get(*address) as u16,
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Watchpoint::Register(register) => write!(f, "V{:X}", register),
+            Watchpoint::Index => write!(f, "I"),
+            Watchpoint::Memory(address) => write!(f, "{:#05X}", address),
+        }
+    }
+}
+
+impl FromStr for Watchpoint {
+    type Err = String;
+
+    /// Parses `v0` to `vf`, `i`, or a memory address
+    fn from_str(name: &str) -> Result<Watchpoint, String> {
+        let lowercase = name.to_ascii_lowercase();
+        if lowercase == "i" {
+            return Ok(Watchpoint::Index);
+        }
+        if let Some(register) = lowercase.strip_prefix('v') {
+            return match u8::from_str_radix(register, 16) {
+                Ok(register) if register < 16 => Ok(Watchpoint::Register(register)),
+                _ => Err(format!("Unknown register '{}'", name)),
+            };
+        }
+        parse_address(name).map(Watchpoint::Memory)
+    }
+}
+
+/// Parses a hexadecimal address, with or without a `0x` prefix.
+fn parse_address(text: &str) -> Result<u16, String> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid address '{}'", text))
+}
+
+/// A command typed at the debugger prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Continue,
+    Step(u32),
+    Break(u16),
+    Delete(u16),
+    Watch(Watchpoint),
+    Unwatch(Watchpoint),
+    List,
+    Registers,
+    Memory { address: u16, length: u16 },
+    Help,
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Command, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (name, arguments) = match words.split_first() {
+            Some((name, arguments)) => (*name, arguments),
+            None => return Err("Empty command".to_string()),
+        };
+        let argument = |index: usize| {
+            arguments
+                .get(index)
+                .copied()
+                .ok_or_else(|| format!("'{}' needs more arguments", name))
+        };
+        let command = match name {
+            "c" | "continue" => Command::Continue,
+            "s" | "step" => match arguments.first() {
+                Some(count) => Command::Step(
+                    count
+                        .parse()
+                        .map_err(|_| format!("Invalid step count '{}'", count))?,
+                ),
+                None => Command::Step(1),
+            },
+            "b" | "break" => Command::Break(parse_address(argument(0)?)?),
+            "d" | "delete" => Command::Delete(parse_address(argument(0)?)?),
+            "w" | "watch" => Command::Watch(argument(0)?.parse()?),
+            "u" | "unwatch" => Command::Unwatch(argument(0)?.parse()?),
+            "l" | "list" => Command::List,
+            "r" | "registers" => Command::Registers,
+            "m" | "memory" => Command::Memory {
+                address: parse_address(argument(0)?)?,
+                length: match arguments.get(1) {
+                    Some(length) => length
+                        .parse()
+                        .map_err(|_| format!("Invalid length '{}'", length))?,
+                    None => 16,
+                },
+            },
+            "h" | "help" => Command::Help,
+            "q" | "quit" => Command::Quit,
+            _ => return Err(format!("Unknown command '{}', try 'help'", name)),
+        };
+        Ok(command)
+    }
+}
+
+/// Why the debugger paused execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Breakpoint(u16),
+    Watchpoint { watchpoint: Watchpoint, old: u16, new: u16 },
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Breakpoint(address) => write!(f, "Breakpoint at {:#05X}", address),
+            StopReason::Watchpoint {
+                watchpoint,
+                old,
+                new,
+            } => write!(f, "{} changed from {:#X} to {:#X}", watchpoint, old, new),
+        }
+    }
+}
+
+/// Breakpoints on the program counter and watchpoints on registers and memory.
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    // Watched values as of the last step
+    watchpoints: BTreeMap<Watchpoint, u16>,
+    paused: bool,
+    // Whether any instruction was stepped yet, as nothing else checks the breakpoint at the start
+    stepped: bool,
+    // Traces every instruction stepped through the debugger
+    tracer: Option<Tracer<Box<dyn Write>>>,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger::default()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    /// Returns false if there already was a breakpoint at the address.
+    pub fn add_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = &u16> {
+        self.breakpoints.iter()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint, cpu: &Cpu) -> bool {
+        self.watchpoints
+            .insert(watchpoint, watchpoint.value(cpu))
+            .is_none()
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        self.watchpoints.remove(&watchpoint).is_some()
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = &Watchpoint> {
+        self.watchpoints.keys()
+    }
+
+    /// Removes every breakpoint and watchpoint.
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+    }
+
+    pub fn set_tracer(&mut self, tracer: Tracer<Box<dyn Write>>) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Tracer<Box<dyn Write>>> {
+        self.tracer.take()
+    }
+
+    /// Executes one instruction and reports whether a watched value changed or the next
+    /// instruction has a breakpoint. Breakpoints are checked after stepping, so continuing from a
+    /// breakpoint does not stop at it again straight away. The very first step instead stops
+    /// without executing anything if the first instruction has a breakpoint.
+    pub fn step(&mut self, cpu: &mut Cpu) -> Result<Option<StopReason>, DecodeError> {
+        if !self.stepped {
+            self.stepped = true;
+            if self.breakpoints.contains(&cpu.pc()) {
+                return Ok(Some(StopReason::Breakpoint(cpu.pc())));
+            }
+        }
+        let result = match &mut self.tracer {
+            Some(tracer) => tracer.step(cpu),
+            None => cpu.step(),
+        };
+        let mut reason = None;
+        // Refresh every watched value, so the next stop only reports newer changes
+        for (watchpoint, value) in self.watchpoints.iter_mut() {
+            let new = watchpoint.value(cpu);
+            if new != *value {
+                reason = reason.or(Some(StopReason::Watchpoint {
+                    watchpoint: *watchpoint,
+                    old: *value,
+                    new,
+                }));
+                *value = new;
+            }
+        }
+        result?;
+        if reason.is_none() && self.breakpoints.contains(&cpu.pc()) {
+            reason = Some(StopReason::Breakpoint(cpu.pc()));
+        }
+        Ok(reason)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::Cpu;
+    use crate::cpu::Memory;
+    use crate::debugger::Command;
+    use crate::debugger::Debugger;
+    use crate::debugger::StopReason;
+    use crate::debugger::Watchpoint;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!("s".parse(), Ok(Command::Step(1)));
+        assert_eq!("step 20".parse(), Ok(Command::Step(20)));
+        assert_eq!("b 0x20A".parse(), Ok(Command::Break(0x20A)));
+        assert_eq!("w vf".parse(), Ok(Command::Watch(Watchpoint::Register(0xF))));
+        assert_eq!("w I".parse(), Ok(Command::Watch(Watchpoint::Index)));
+        assert_eq!("u 300".parse(), Ok(Command::Unwatch(Watchpoint::Memory(0x300))));
+        assert_eq!(
+            "m 200 4".parse(),
+            Ok(Command::Memory {
+                address: 0x200,
+                length: 4
+            })
+        );
+        assert!("b".parse::<Command>().is_err());
+        assert!("w v10".parse::<Command>().is_err());
+        assert!("jump".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn test_breakpoints_and_watchpoints() {
+        let rom = vec![
+            0x60, 0x01, // v0 := 1
+            0xA3, 0x00, // i := 0x300
+            0xF0, 0x55, // save v0
+            0x12, 0x00, // jump 0x200
+        ];
+        let mut cpu = Cpu::new(Memory::new(rom).unwrap(), Quirks::default());
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x206);
+        debugger.add_watchpoint(Watchpoint::Memory(0x300), &cpu);
+
+        let mut stops = vec![];
+        for _ in 0..8 {
+            if let Some(reason) = debugger.step(&mut cpu).unwrap() {
+                stops.push(reason);
+            }
+        }
+        assert_eq!(
+            stops,
+            [
+                StopReason::Watchpoint {
+                    watchpoint: Watchpoint::Memory(0x300),
+                    old: 0,
+                    new: 1
+                },
+                StopReason::Breakpoint(0x206),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_breakpoint_at_start() {
+        let rom = vec![
+            0x60, 0x05, // v0 := 5
+            0x12, 0x00, // jump 0x200
+        ];
+        let mut cpu = Cpu::new(Memory::new(rom).unwrap(), Quirks::default());
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x200);
+        assert_eq!(debugger.step(&mut cpu), Ok(Some(StopReason::Breakpoint(0x200))));
+        assert_eq!(cpu.pc(), 0x200);
+        assert_eq!(cpu.registers()[0], 0);
+        // Continuing from the breakpoint executes the instruction
+        assert_eq!(debugger.step(&mut cpu), Ok(None));
+        assert_eq!(cpu.registers()[0], 5);
+        assert_eq!(debugger.step(&mut cpu), Ok(Some(StopReason::Breakpoint(0x200))));
+    }
+}
@@ -0,0 +1,476 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::cpu::Cpu;
+use crate::cpu::START_ADDRESS;
+use crate::instruction::Instruction;
+
+// Data bytes printed on one line of a listing
+const DATA_ROW_LENGTH: usize = 8;
+
+/// Mnemonic style of a listing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// Octo assembly, e.g. `v0 += 0x01`
+    Octo,
+    /// Cowgod's technical reference, e.g. `ADD V0, #01`
+    Cowgod,
+}
+
+impl Syntax {
+    pub const ALL: [Syntax; 2] = [Syntax::Octo, Syntax::Cowgod];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Syntax::Octo => "octo",
+            Syntax::Cowgod => "cowgod",
+        }
+    }
+}
+
+impl fmt::Display for Syntax {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Syntax, String> {
+        match Syntax::ALL.iter().find(|syntax| syntax.name() == name) {
+            Some(syntax) => Ok(*syntax),
+            None => {
+                let names: Vec<&str> = Syntax::ALL.iter().map(|syntax| syntax.name()).collect();
+                Err(format!("Unknown syntax '{}', expected one of: {}", name, names.join(", ")))
+            }
+        }
+    }
+}
+
+/// One line of a listing, either an instruction or a run of data bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub address: u16,
+    // Opcode words of an instruction, empty for data
+    pub words: Vec<u16>,
+    pub text: String,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let words: Vec<String> = self.words.iter().map(|word| format!("{:04X}", word)).collect();
+        write!(f, "{:04X}:  {:<9}  {}", self.address, words.join(" "), self.text)
+    }
+}
+
+/// Decodes every word of the ROM in order. Words that are not instructions are listed as data.
+pub fn disassemble(rom: &[u8], syntax: Syntax) -> Vec<Line> {
+    let mut lines = vec![];
+    let mut address = START_ADDRESS;
+    while let Some(offset) = offset_of(rom, address) {
+        match decode(rom, address, syntax) {
+            Some(line) => {
+                address = address.wrapping_add(2 * line.words.len() as u16);
+                lines.push(line);
+            }
+            None => {
+                let length = (rom.len() - offset).min(2);
+                lines.push(data_line(address, &rom[offset..offset + length], syntax));
+                address = address.wrapping_add(length as u16);
+            }
+        }
+    }
+    lines
+}
+
+/// Lists only the instructions reachable from the entry point by following jumps, calls and
+/// skips, and everything else as data. Targets of `jump0` cannot be known statically, so code
+/// only reached through it shows up as data.
+pub fn disassemble_reachable(rom: &[u8], syntax: Syntax) -> Vec<Line> {
+    let code = find_code(rom);
+    let mut lines = vec![];
+    let mut address = START_ADDRESS;
+    while let Some(offset) = offset_of(rom, address) {
+        if code.contains(&address) {
+            let line = decode(rom, address, syntax).expect("Reachable code decodes");
+            address = address.wrapping_add(2 * line.words.len() as u16);
+            lines.push(line);
+            continue;
+        }
+        let mut length = 1;
+        while length < DATA_ROW_LENGTH
+            && offset + length < rom.len()
+            && !code.contains(&address.wrapping_add(length as u16))
+        {
+            length += 1;
+        }
+        lines.push(data_line(address, &rom[offset..offset + length], syntax));
+        address = address.wrapping_add(length as u16);
+    }
+    lines
+}
+
+fn offset_of(rom: &[u8], address: u16) -> Option<usize> {
+    let offset = address.checked_sub(START_ADDRESS)? as usize;
+    if offset < rom.len() {
+        Some(offset)
+    } else {
+        None
+    }
+}
+
+fn word_at(rom: &[u8], address: u16) -> Option<u16> {
+    let offset = offset_of(rom, address)?;
+    let bytes = rom.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// Decodes the instruction at the address, including the operand of `i := long`.
+fn decode(rom: &[u8], address: u16, syntax: Syntax) -> Option<Line> {
+    let raw = word_at(rom, address)?;
+    let instruction = Cpu::decode_at(raw, address).ok()?;
+    let mut words = vec![raw];
+    if let Instruction::SetILong = instruction {
+        words.push(word_at(rom, address.wrapping_add(2))?);
+    }
+    let text = format_instruction(&instruction, *words.last().unwrap(), syntax);
+    Some(Line {
+        address,
+        words,
+        text,
+    })
+}
+
+fn data_line(address: u16, bytes: &[u8], syntax: Syntax) -> Line {
+    let text = match syntax {
+        Syntax::Octo => {
+            let bytes: Vec<String> = bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+            bytes.join(" ")
+        }
+        Syntax::Cowgod => {
+            let bytes: Vec<String> = bytes.iter().map(|byte| format!("#{:02X}", byte)).collect();
+            format!("DB {}", bytes.join(", "))
+        }
+    };
+    Line {
+        address,
+        words: vec![],
+        text,
+    }
+}
+
+/// Start addresses of every instruction reachable from the entry point.
+fn find_code(rom: &[u8]) -> BTreeSet<u16> {
+    let mut code = BTreeSet::new();
+    let mut pending = vec![START_ADDRESS];
+    while let Some(address) = pending.pop() {
+        if code.contains(&address) {
+            continue;
+        }
+        let instruction = match word_at(rom, address).map(|raw| Cpu::decode_at(raw, address)) {
+            Some(Ok(instruction)) => instruction,
+            _ => continue,
+        };
+        if let Instruction::SetILong = instruction {
+            if word_at(rom, address.wrapping_add(2)).is_none() {
+                continue;
+            }
+        }
+        code.insert(address);
+
+        let next = address.wrapping_add(2);
+        match instruction {
+            Instruction::Jump(target) => pending.push(target),
+            Instruction::SubroutineCall(target) => pending.extend([target, next]),
+            Instruction::SubroutineReturn
+            | Instruction::Exit
+            | Instruction::JumpWithOffset { .. } => {}
+            Instruction::SetILong => pending.push(address.wrapping_add(4)),
+            Instruction::NoopImmediateEq { .. }
+            | Instruction::NoopImmediateNotEq { .. }
+            | Instruction::NoopRegisterEq { .. }
+            | Instruction::NoopRegisterNotEq { .. }
+            | Instruction::NoopVXDown(_)
+            | Instruction::NoopVXNotDown(_) => {
+                // Skips jump over the whole of a following `i := long`
+                let skipped = if word_at(rom, next) == Some(0xF000) { 4 } else { 2 };
+                pending.extend([next, next.wrapping_add(skipped)]);
+            }
+            _ => pending.push(next),
+        }
+    }
+    code
+}
+
+/// Formats an instruction, with `operand` being the word following `SetILong`.
+fn format_instruction(instruction: &Instruction, operand: u16, syntax: Syntax) -> String {
+    match syntax {
+        Syntax::Octo => format_octo(instruction, operand),
+        Syntax::Cowgod => format_cowgod(instruction, operand),
+    }
+}
+
+fn format_octo(instruction: &Instruction, operand: u16) -> String {
+    use Instruction::*;
+    match *instruction {
+        ClearScreen => "clear".to_string(),
+        ScrollDown(n) => format!("scroll-down {}", n),
+        ScrollUp(n) => format!("scroll-up {}", n),
+        ScrollRight => "scroll-right".to_string(),
+        ScrollLeft => "scroll-left".to_string(),
+        Exit => "exit".to_string(),
+        LowResolution => "lores".to_string(),
+        HighResolution => "hires".to_string(),
+        Jump(address) => format!("jump 0x{:03X}", address),
+        SubroutineReturn => "return".to_string(),
+        SubroutineCall(address) => format!(":call 0x{:03X}", address),
+        SetVX { register, value } => format!("v{:x} := 0x{:02X}", register, value),
+        AddVX { register, value } => format!("v{:x} += 0x{:02X}", register, value),
+        SetI(address) => format!("i := 0x{:03X}", address),
+        SetILong => format!("i := long 0x{:04X}", operand),
+        DisplayDraw {
+            register_x,
+            register_y,
+            n,
+        } => format!("sprite v{:x} v{:x} {}", register_x, register_y, n),
+        // Octo writes skips as the condition under which the next instruction runs
+        NoopImmediateEq { register, value } => format!("if v{:x} != 0x{:02X} then", register, value),
+        NoopImmediateNotEq { register, value } => format!("if v{:x} == 0x{:02X} then", register, value),
+        NoopRegisterEq {
+            register_x,
+            register_y,
+        } => format!("if v{:x} != v{:x} then", register_x, register_y),
+        NoopRegisterNotEq {
+            register_x,
+            register_y,
+        } => format!("if v{:x} == v{:x} then", register_x, register_y),
+        ArithmeticSet {
+            register_x,
+            register_y,
+        } => format!("v{:x} := v{:x}", register_x, register_y),
+        ArithmeticOr {
+            register_x,
+            register_y,
+        } => format!("v{:x} |= v{:x}", register_x, register_y),
+        ArithmeticAnd {
+            register_x,
+            register_y,
+        } => format!("v{:x} &= v{:x}", register_x, register_y),
+        ArithmeticXor {
+            register_x,
+            register_y,
+        } => format!("v{:x} ^= v{:x}", register_x, register_y),
+        ArithmeticAdd {
+            register_x,
+            register_y,
+        } => format!("v{:x} += v{:x}", register_x, register_y),
+        ArithmeticSubtractXY {
+            register_x,
+            register_y,
+        } => format!("v{:x} -= v{:x}", register_x, register_y),
+        ArithmeticSubtractYX {
+            register_x,
+            register_y,
+        } => format!("v{:x} =- v{:x}", register_x, register_y),
+        ArithmeticShiftLeft {
+            register_x,
+            register_y,
+        } => format!("v{:x} <<= v{:x}", register_x, register_y),
+        ArithmeticShiftRight {
+            register_x,
+            register_y,
+        } => format!("v{:x} >>= v{:x}", register_x, register_y),
+        Store(register) => format!("save v{:x}", register),
+        Load(register) => format!("load v{:x}", register),
+        StoreRange {
+            register_x,
+            register_y,
+        } => format!("save v{:x} - v{:x}", register_x, register_y),
+        LoadRange {
+            register_x,
+            register_y,
+        } => format!("load v{:x} - v{:x}", register_x, register_y),
+        BcdConversion(register) => format!("bcd v{:x}", register),
+        FontCharacter(register) => format!("i := hex v{:x}", register),
+        BigFontCharacter(register) => format!("i := bighex v{:x}", register),
+        AddToIndex(register) => format!("i += v{:x}", register),
+        SetVXFromDelayTimer(register) => format!("v{:x} := delay", register),
+        SetDelayTimerFromVX(register) => format!("delay := v{:x}", register),
+        SetSoundTimerFromVX(register) => format!("buzzer := v{:x}", register),
+        LoadAudioPattern => "audio".to_string(),
+        SetPitch(register) => format!("pitch := v{:x}", register),
+        NoopVXDown(register) => format!("if v{:x} -key then", register),
+        NoopVXNotDown(register) => format!("if v{:x} key then", register),
+        JumpWithOffset { address, .. } => format!("jump0 0x{:03X}", address),
+        GetKey(register) => format!("v{:x} := key", register),
+        Random { register_x, mask } => format!("v{:x} := random 0x{:02X}", register_x, mask),
+        StoreFlags(register) => format!("saveflags v{:x}", register),
+        LoadFlags(register) => format!("loadflags v{:x}", register),
+        SelectPlanes(planes) => format!("plane {}", planes),
+    }
+}
+
+fn format_cowgod(instruction: &Instruction, operand: u16) -> String {
+    use Instruction::*;
+    match *instruction {
+        ClearScreen => "CLS".to_string(),
+        ScrollDown(n) => format!("SCD {}", n),
+        ScrollUp(n) => format!("SCU {}", n),
+        ScrollRight => "SCR".to_string(),
+        ScrollLeft => "SCL".to_string(),
+        Exit => "EXIT".to_string(),
+        LowResolution => "LOW".to_string(),
+        HighResolution => "HIGH".to_string(),
+        Jump(address) => format!("JP #{:03X}", address),
+        SubroutineReturn => "RET".to_string(),
+        SubroutineCall(address) => format!("CALL #{:03X}", address),
+        SetVX { register, value } => format!("LD V{:X}, #{:02X}", register, value),
+        AddVX { register, value } => format!("ADD V{:X}, #{:02X}", register, value),
+        SetI(address) => format!("LD I, #{:03X}", address),
+        SetILong => format!("LD I, LONG #{:04X}", operand),
+        DisplayDraw {
+            register_x,
+            register_y,
+            n,
+        } => format!("DRW V{:X}, V{:X}, {}", register_x, register_y, n),
+        NoopImmediateEq { register, value } => format!("SE V{:X}, #{:02X}", register, value),
+        NoopImmediateNotEq { register, value } => format!("SNE V{:X}, #{:02X}", register, value),
+        NoopRegisterEq {
+            register_x,
+            register_y,
+        } => format!("SE V{:X}, V{:X}", register_x, register_y),
+        NoopRegisterNotEq {
+            register_x,
+            register_y,
+        } => format!("SNE V{:X}, V{:X}", register_x, register_y),
+        ArithmeticSet {
+            register_x,
+            register_y,
+        } => format!("LD V{:X}, V{:X}", register_x, register_y),
+        ArithmeticOr {
+            register_x,
+            register_y,
+        } => format!("OR V{:X}, V{:X}", register_x, register_y),
+        ArithmeticAnd {
+            register_x,
+            register_y,
+        } => format!("AND V{:X}, V{:X}", register_x, register_y),
+        ArithmeticXor {
+            register_x,
+            register_y,
+        } => format!("XOR V{:X}, V{:X}", register_x, register_y),
+        ArithmeticAdd {
+            register_x,
+            register_y,
+        } => format!("ADD V{:X}, V{:X}", register_x, register_y),
+        ArithmeticSubtractXY {
+            register_x,
+            register_y,
+        } => format!("SUB V{:X}, V{:X}", register_x, register_y),
+        ArithmeticSubtractYX {
+            register_x,
+            register_y,
+        } => format!("SUBN V{:X}, V{:X}", register_x, register_y),
+        ArithmeticShiftLeft {
+            register_x,
+            register_y,
+        } => format!("SHL V{:X}, V{:X}", register_x, register_y),
+        ArithmeticShiftRight {
+            register_x,
+            register_y,
+        } => format!("SHR V{:X}, V{:X}", register_x, register_y),
+        Store(register) => format!("LD [I], V{:X}", register),
+        Load(register) => format!("LD V{:X}, [I]", register),
+        StoreRange {
+            register_x,
+            register_y,
+        } => format!("SAVE V{:X}-V{:X}", register_x, register_y),
+        LoadRange {
+            register_x,
+            register_y,
+        } => format!("LOAD V{:X}-V{:X}", register_x, register_y),
+        BcdConversion(register) => format!("LD B, V{:X}", register),
+        FontCharacter(register) => format!("LD F, V{:X}", register),
+        BigFontCharacter(register) => format!("LD HF, V{:X}", register),
+        AddToIndex(register) => format!("ADD I, V{:X}", register),
+        SetVXFromDelayTimer(register) => format!("LD V{:X}, DT", register),
+        SetDelayTimerFromVX(register) => format!("LD DT, V{:X}", register),
+        SetSoundTimerFromVX(register) => format!("LD ST, V{:X}", register),
+        LoadAudioPattern => "AUDIO".to_string(),
+        SetPitch(register) => format!("PITCH V{:X}", register),
+        NoopVXDown(register) => format!("SKP V{:X}", register),
+        NoopVXNotDown(register) => format!("SKNP V{:X}", register),
+        JumpWithOffset {
+            register_x,
+            address,
+        } => format!("JP V{:X}, #{:03X}", register_x, address),
+        GetKey(register) => format!("LD V{:X}, K", register),
+        Random { register_x, mask } => format!("RND V{:X}, #{:02X}", register_x, mask),
+        StoreFlags(register) => format!("LD R, V{:X}", register),
+        LoadFlags(register) => format!("LD V{:X}, R", register),
+        SelectPlanes(planes) => format!("PLANE {}", planes),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::disasm::disassemble;
+    use crate::disasm::disassemble_reachable;
+    use crate::disasm::Line;
+    use crate::disasm::Syntax;
+
+    const ROM: [u8; 14] = [
+        0x22, 0x06, // :call 0x206
+        0x12, 0x02, // jump 0x202
+        0x81, 0x81, // sprite data
+        0x3A, 0x07, // if va != 7 then
+        0xF0, 0x00, 0x12, 0x34, // i := long 0x1234
+        0x00, 0xEE, // return
+    ];
+
+    fn texts(lines: Vec<Line>) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_disassemble_linear() {
+        assert_eq!(
+            texts(disassemble(&ROM, Syntax::Octo)),
+            [
+                "0200:  2206       :call 0x206",
+                "0202:  1202       jump 0x202",
+                "0204:  8181       v1 |= v8",
+                "0206:  3A07       if va != 0x07 then",
+                "0208:  F000 1234  i := long 0x1234",
+                "020C:  00EE       return",
+            ]
+        );
+        assert_eq!(
+            texts(disassemble(&[0x5A, 0xB4, 0x01], Syntax::Cowgod)),
+            ["0200:             DB #5A, #B4", "0202:             DB #01"]
+        );
+    }
+
+    #[test]
+    fn test_disassemble_reachable() {
+        assert_eq!(
+            texts(disassemble_reachable(&ROM, Syntax::Cowgod)),
+            [
+                "0200:  2206       CALL #206",
+                "0202:  1202       JP #202",
+                "0204:             DB #81, #81",
+                "0206:  3A07       SE VA, #07",
+                "0208:  F000 1234  LD I, LONG #1234",
+                "020C:  00EE       RET",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_syntax_names() {
+        assert_eq!("cowgod".parse(), Ok(Syntax::Cowgod));
+        assert!("intel".parse::<Syntax>().is_err());
+    }
+}
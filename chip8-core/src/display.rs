@@ -0,0 +1,203 @@
+use crate::error::SavestateError;
+use crate::savestate::StateReader;
+use crate::savestate::StateWriter;
+
+const LORES_WIDTH: usize = 64;
+const LORES_HEIGHT: usize = 32;
+const HIRES_WIDTH: usize = 128;
+const HIRES_HEIGHT: usize = 64;
+
+/// Number of XO-CHIP bitplanes, combined into a 2-bit palette index per pixel
+pub const PLANE_COUNT: u8 = 2;
+
+pub struct VRAM {
+    pub width: u8,
+    pub height: u8,
+    hires: bool,
+    // Bit N of each cell is the pixel in plane N
+    data: Vec<u8>,
+    // Planes affected by drawing, clearing and scrolling (XO-CHIP FN01)
+    selected_planes: u8,
+}
+
+impl VRAM {
+    pub fn new() -> VRAM {
+        VRAM {
+            width: LORES_WIDTH as u8,
+            height: LORES_HEIGHT as u8,
+            hires: false,
+            data: vec![0; LORES_WIDTH * LORES_HEIGHT],
+            selected_planes: 0b01,
+        }
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Whether the pixel is set in any plane
+    pub fn get_cell(&self, x: u8, y: u8) -> bool {
+        self.get_pixel(x, y) != 0
+    }
+
+    /// The palette index of the pixel, with bit N set if the pixel is set in plane N
+    pub fn get_pixel(&self, x: u8, y: u8) -> u8 {
+        self.data[self.cell_index(x, y)]
+    }
+
+    pub fn selected_planes(&self) -> u8 {
+        self.selected_planes
+    }
+
+    pub(crate) fn select_planes(&mut self, planes: u8) {
+        self.selected_planes = planes & ((1 << PLANE_COUNT) - 1);
+    }
+
+    pub(crate) fn get_plane_cell(&self, plane: u8, x: u8, y: u8) -> bool {
+        self.get_pixel(x, y) & (1 << plane) != 0
+    }
+
+    pub(crate) fn set_plane_cell(&mut self, plane: u8, x: u8, y: u8, value: bool) {
+        let index = self.cell_index(x, y);
+        if value {
+            self.data[index] |= 1 << plane;
+        } else {
+            self.data[index] &= !(1 << plane);
+        }
+    }
+
+    /// Clears the selected planes
+    pub(crate) fn clear(&mut self) {
+        let keep = !self.selected_planes;
+        for cell in self.data.iter_mut() {
+            *cell &= keep;
+        }
+    }
+
+    /// Switches between the 64x32 and the SUPER-CHIP 128x64 mode, clearing all planes.
+    pub(crate) fn set_hires(&mut self, hires: bool) {
+        let (width, height) = if hires {
+            (HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            (LORES_WIDTH, LORES_HEIGHT)
+        };
+        self.hires = hires;
+        self.width = width as u8;
+        self.height = height as u8;
+        self.data.clear();
+        self.data.resize(width * height, 0);
+    }
+
+    pub(crate) fn scroll_down(&mut self, rows: u8) {
+        self.scroll(0, rows as isize);
+    }
+
+    pub(crate) fn scroll_up(&mut self, rows: u8) {
+        self.scroll(0, -(rows as isize));
+    }
+
+    pub(crate) fn scroll_right(&mut self, columns: u8) {
+        self.scroll(columns as isize, 0);
+    }
+
+    pub(crate) fn scroll_left(&mut self, columns: u8) {
+        self.scroll(-(columns as isize), 0);
+    }
+
+    /// Moves the selected planes by the given offset, filling uncovered pixels with 0.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let width = self.width as isize;
+        let height = self.height as isize;
+        let selected = self.selected_planes;
+        let previous = self.data.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let (source_x, source_y) = (x - dx, y - dy);
+                let source = if (0..width).contains(&source_x) && (0..height).contains(&source_y) {
+                    previous[(source_y * width + source_x) as usize]
+                } else {
+                    0
+                };
+                let index = (y * width + x) as usize;
+                self.data[index] = (previous[index] & !selected) | (source & selected);
+            }
+        }
+    }
+
+    fn cell_index(&self, x: u8, y: u8) -> usize {
+        y as usize * self.width as usize + x as usize
+    }
+
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.hires);
+        writer.write_u8(self.selected_planes);
+        writer.write_bytes(&self.data);
+    }
+
+    pub(crate) fn load_state(reader: &mut StateReader) -> Result<VRAM, SavestateError> {
+        let mut vram = VRAM::new();
+        vram.set_hires(reader.read_bool()?);
+        vram.select_planes(reader.read_u8()?);
+        let data = reader.read_bytes()?;
+        if data.len() != vram.data.len() {
+            return Err(SavestateError::Invalid(format!("VRAM is {} bytes", data.len())));
+        }
+        vram.data.copy_from_slice(data);
+        Ok(vram)
+    }
+}
+
+impl Default for VRAM {
+    fn default() -> Self {
+        VRAM::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::display::VRAM;
+
+    #[test]
+    fn test_set_hires() {
+        let mut vram = VRAM::new();
+        vram.set_plane_cell(0, 3, 3, true);
+        vram.set_hires(true);
+        assert_eq!((vram.width, vram.height), (128, 64));
+        assert!(!vram.get_cell(3, 3));
+        vram.set_hires(false);
+        assert_eq!((vram.width, vram.height), (64, 32));
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut vram = VRAM::new();
+        vram.set_plane_cell(0, 10, 5, true);
+        vram.scroll_down(3);
+        assert!(vram.get_cell(10, 8));
+        assert!(!vram.get_cell(10, 5));
+        vram.scroll_right(4);
+        assert!(vram.get_cell(14, 8));
+        vram.scroll_left(4);
+        vram.scroll_left(4);
+        assert!(vram.get_cell(6, 8));
+        vram.scroll_left(8);
+        assert!((0..vram.width).all(|x| !vram.get_cell(x, 8)));
+    }
+
+    #[test]
+    fn test_planes() {
+        let mut vram = VRAM::new();
+        vram.set_plane_cell(0, 1, 1, true);
+        vram.set_plane_cell(1, 1, 1, true);
+        assert_eq!(vram.get_pixel(1, 1), 0b11);
+
+        vram.select_planes(0b10);
+        vram.scroll_up(1);
+        assert_eq!(vram.get_pixel(1, 1), 0b01);
+        assert_eq!(vram.get_pixel(1, 0), 0b10);
+
+        vram.clear();
+        assert_eq!(vram.get_pixel(1, 0), 0);
+        assert_eq!(vram.get_pixel(1, 1), 0b01);
+    }
+}
@@ -0,0 +1,85 @@
+use std::fmt::Write;
+
+use crate::cpu::Cpu;
+use crate::cpu::Memory;
+use crate::display::VRAM;
+
+const MEMORY_ROW_LENGTH: usize = 16;
+
+/// Formats PC, I, the V registers, call stack and timers.
+pub fn format_registers(cpu: &Cpu) -> String {
+    let mut output = String::new();
+    writeln!(output, "PC: {:#05X}  I: {:#05X}", cpu.pc(), cpu.index_register()).unwrap();
+    for (i, value) in cpu.registers().iter().enumerate() {
+        write!(output, "V{:X}: {:#04X} ", i, value).unwrap();
+        if i % 8 == 7 {
+            writeln!(output).unwrap();
+        }
+    }
+    writeln!(output, "Call stack: {:X?}", cpu.call_stack()).unwrap();
+    writeln!(
+        output,
+        "Delay timer: {}  Sound timer: {}",
+        cpu.delay_timer(),
+        cpu.sound_timer()
+    )
+    .unwrap();
+    output
+}
+
+/// Formats the screen with one character per pixel: `.` when unset, `#` when set in the first
+/// plane only, and otherwise the palette index of the pixel.
+pub fn format_vram(vram: &VRAM) -> String {
+    let mut output = String::new();
+    for y in 0..vram.height {
+        for x in 0..vram.width {
+            let character = match vram.get_pixel(x, y) {
+                0 => '.',
+                1 => '#',
+                pixel => char::from_digit(pixel as u32, 16).unwrap(),
+            };
+            output.push(character);
+        }
+        output.push('\n');
+    }
+    output
+}
+
+/// Formats memory as a hex dump, collapsing runs of all-zero rows into a single `*` line.
+pub fn format_memory(memory: &Memory) -> String {
+    let mut output = String::new();
+    let mut skipping = false;
+    for (row, bytes) in memory.data().chunks(MEMORY_ROW_LENGTH).enumerate() {
+        if bytes.iter().all(|byte| *byte == 0) {
+            if !skipping {
+                writeln!(output, "*").unwrap();
+            }
+            skipping = true;
+            continue;
+        }
+        skipping = false;
+        write!(output, "{:04X}:", row * MEMORY_ROW_LENGTH).unwrap();
+        for byte in bytes {
+            write!(output, " {:02X}", byte).unwrap();
+        }
+        writeln!(output).unwrap();
+    }
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::Memory;
+    use crate::dump::format_memory;
+
+    #[test]
+    fn test_format_memory() {
+        let memory = Memory::new(vec![0x12, 0x34]).unwrap();
+        let output = format_memory(&memory);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0], "*");
+        assert!(lines[1].starts_with("0050: F0 90 90 90 F0 20 60 20"));
+        assert!(lines.contains(&"0200: 12 34 00 00 00 00 00 00 00 00 00 00 00 00 00 00"));
+        assert_eq!(lines.last(), Some(&"*"));
+    }
+}
@@ -0,0 +1,85 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeErrorKind {
+    UnknownInstruction,
+    UnknownArithmeticInstruction,
+    UnknownEPrefixInstruction,
+    UnknownFPrefixInstruction,
+    StackUnderflow,
+    StackOverflow,
+    InvalidKey(u8),
+}
+
+/// An opcode that could not be decoded or executed, along with the address it was fetched from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub kind: DecodeErrorKind,
+    pub opcode: u16,
+    pub pc: u16,
+}
+
+impl DecodeError {
+    pub fn new(kind: DecodeErrorKind, opcode: u16, pc: u16) -> DecodeError {
+        DecodeError { kind, opcode, pc }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            DecodeErrorKind::UnknownInstruction => write!(f, "Unknown instruction")?,
+            DecodeErrorKind::UnknownArithmeticInstruction => {
+                write!(f, "Unknown arithmetic instruction")?
+            }
+            DecodeErrorKind::UnknownEPrefixInstruction => write!(f, "Unknown E-prefix instruction")?,
+            DecodeErrorKind::UnknownFPrefixInstruction => write!(f, "Unknown F-prefix instruction")?,
+            DecodeErrorKind::StackUnderflow => write!(f, "Return with empty call stack")?,
+            DecodeErrorKind::StackOverflow => write!(f, "Call with full call stack")?,
+            DecodeErrorKind::InvalidKey(key) => write!(f, "Invalid key {:#04X}", key)?,
+        }
+        write!(f, ": {:#06X} at {:#05X}", self.opcode, self.pc)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// A savestate that could not be restored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SavestateError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnexpectedEnd,
+    Invalid(String),
+}
+
+impl fmt::Display for SavestateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SavestateError::BadMagic => write!(f, "Not a savestate"),
+            SavestateError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported savestate version {}", version)
+            }
+            SavestateError::UnexpectedEnd => write!(f, "Savestate is truncated"),
+            SavestateError::Invalid(reason) => write!(f, "Invalid savestate: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SavestateError {}
+
+/// A problem in assembly or Octo source, located by file and 1-based line number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
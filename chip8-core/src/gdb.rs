@@ -0,0 +1,451 @@
+use std::fmt::Write as _;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+
+use crate::cpu::Cpu;
+use crate::debugger::Debugger;
+use crate::debugger::StopReason;
+use crate::debugger::Watchpoint;
+
+// GDB register numbers: V0 to VF, then I, PC and the call stack depth
+const REGISTER_I: usize = 16;
+const REGISTER_PC: usize = 17;
+const REGISTER_SP: usize = 18;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+<feature name="org.chip8.cpu">
+<reg name="v0" bitsize="8" type="uint8"/>
+<reg name="v1" bitsize="8" type="uint8"/>
+<reg name="v2" bitsize="8" type="uint8"/>
+<reg name="v3" bitsize="8" type="uint8"/>
+<reg name="v4" bitsize="8" type="uint8"/>
+<reg name="v5" bitsize="8" type="uint8"/>
+<reg name="v6" bitsize="8" type="uint8"/>
+<reg name="v7" bitsize="8" type="uint8"/>
+<reg name="v8" bitsize="8" type="uint8"/>
+<reg name="v9" bitsize="8" type="uint8"/>
+<reg name="va" bitsize="8" type="uint8"/>
+<reg name="vb" bitsize="8" type="uint8"/>
+<reg name="vc" bitsize="8" type="uint8"/>
+<reg name="vd" bitsize="8" type="uint8"/>
+<reg name="ve" bitsize="8" type="uint8"/>
+<reg name="vf" bitsize="8" type="uint8"/>
+<reg name="i" bitsize="16" type="data_ptr"/>
+<reg name="pc" bitsize="16" type="code_ptr"/>
+<reg name="sp" bitsize="8" type="uint8"/>
+</feature>
+</target>
+"#;
+
+/// State of the connection after processing incoming packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Session {
+    Attached,
+    Detached,
+    /// GDB asked to terminate the program
+    Killed,
+}
+
+enum Input {
+    Packet(String),
+    // Ctrl-C in GDB
+    Interrupt,
+}
+
+enum Reply {
+    Packet(String),
+    Resume,
+    StartNoAck,
+    Detach,
+    Kill,
+}
+
+/// Server side of the GDB remote serial protocol for a single connection.
+///
+/// The stub drives the CPU through a `Debugger`: the CPU is stopped for GDB whenever the debugger
+/// is paused, and GDB's breakpoints and write watchpoints become the debugger's. Frontends keep
+/// running frames while the debugger is not paused, and call `report_stop` when it pauses.
+pub struct GdbStub {
+    stream: TcpStream,
+    input: Vec<u8>,
+    no_ack: bool,
+}
+
+impl GdbStub {
+    /// Waits for GDB to connect to the port on localhost.
+    pub fn listen(port: u16) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let (stream, _) = listener.accept()?;
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(GdbStub {
+            stream,
+            input: vec![],
+            no_ack: false,
+        })
+    }
+
+    /// Handles all packets received since the last call without blocking.
+    pub fn poll(&mut self, cpu: &mut Cpu, debugger: &mut Debugger) -> io::Result<Session> {
+        let mut chunk = [0; 4096];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Ok(self.close(debugger)),
+                Ok(length) => self.input.extend_from_slice(&chunk[..length]),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) => return Err(error),
+            }
+        }
+
+        while let Some(input) = self.next_input() {
+            let packet = match input {
+                Input::Interrupt => {
+                    if !debugger.is_paused() {
+                        debugger.pause();
+                        self.report_stop(None)?;
+                    }
+                    continue;
+                }
+                Input::Packet(packet) => packet,
+            };
+            if !self.no_ack {
+                self.stream.write_all(b"+")?;
+            }
+            match handle_packet(&packet, cpu, debugger) {
+                Reply::Packet(reply) => self.send(&reply)?,
+                Reply::Resume => debugger.resume(),
+                Reply::StartNoAck => {
+                    self.send("OK")?;
+                    self.no_ack = true;
+                }
+                Reply::Detach => {
+                    self.send("OK")?;
+                    debugger.resume();
+                    return Ok(Session::Detached);
+                }
+                Reply::Kill => return Ok(Session::Killed),
+            }
+        }
+        Ok(Session::Attached)
+    }
+
+    /// Ends a session GDB closed without `D` or `k`. Nobody reads replies any more, so only a
+    /// kill sent before closing is honoured, otherwise the program runs on as after `D`.
+    fn close(&mut self, debugger: &mut Debugger) -> Session {
+        while let Some(input) = self.next_input() {
+            if matches!(input, Input::Packet(packet) if packet == "k") {
+                return Session::Killed;
+            }
+        }
+        debugger.clear();
+        debugger.resume();
+        Session::Detached
+    }
+
+    /// Tells GDB that the CPU stopped, for instance on a breakpoint hit while running frames.
+    pub fn report_stop(&mut self, reason: Option<StopReason>) -> io::Result<()> {
+        self.send(&stop_reply(reason))
+    }
+
+    fn next_input(&mut self) -> Option<Input> {
+        loop {
+            match *self.input.first()? {
+                0x03 => {
+                    self.input.remove(0);
+                    return Some(Input::Interrupt);
+                }
+                b'$' => {
+                    // The checksum is not verified, TCP already guarantees integrity
+                    let end = self.input.iter().position(|byte| *byte == b'#')?;
+                    if self.input.len() < end + 3 {
+                        return None;
+                    }
+                    let packet = String::from_utf8_lossy(&self.input[1..end]).into_owned();
+                    self.input.drain(..end + 3);
+                    return Some(Input::Packet(packet));
+                }
+                // Acknowledgements and anything unexpected
+                _ => {
+                    self.input.remove(0);
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        let packet = format!("${}#{:02x}", data, checksum);
+        self.stream.write_all(packet.as_bytes())
+    }
+}
+
+fn stop_reply(reason: Option<StopReason>) -> String {
+    match reason {
+        Some(StopReason::Watchpoint {
+            watchpoint: Watchpoint::Memory(address),
+            ..
+        }) => format!("T05watch:{:x};", address),
+        // SIGTRAP
+        _ => "S05".to_string(),
+    }
+}
+
+fn handle_packet(packet: &str, cpu: &mut Cpu, debugger: &mut Debugger) -> Reply {
+    let (command, arguments) = packet.split_at(packet.len().min(1));
+    let reply = match command {
+        "?" => stop_reply(None),
+        "g" => {
+            let mut registers = String::new();
+            for register in 0..=REGISTER_SP {
+                registers += &read_register(cpu, register);
+            }
+            registers
+        }
+        "G" => {
+            let bytes = match decode_hex(arguments) {
+                Some(bytes) if bytes.len() >= 20 => bytes,
+                _ => return error(),
+            };
+            for (register, value) in bytes[..16].iter().enumerate() {
+                cpu.set_register(register as u8, *value);
+            }
+            cpu.set_index_register(u16::from_le_bytes([bytes[16], bytes[17]]));
+            cpu.set_pc(u16::from_le_bytes([bytes[18], bytes[19]]));
+            "OK".to_string()
+        }
+        "p" => match usize::from_str_radix(arguments, 16) {
+            Ok(register) if register <= REGISTER_SP => read_register(cpu, register),
+            _ => return error(),
+        },
+        "P" => {
+            let parsed = arguments.split_once('=').and_then(|(register, value)| {
+                Some((usize::from_str_radix(register, 16).ok()?, decode_hex(value)?))
+            });
+            match parsed {
+                Some((register, value)) if register < 16 && value.len() == 1 => {
+                    cpu.set_register(register as u8, value[0])
+                }
+                Some((REGISTER_I, value)) if value.len() == 2 => {
+                    cpu.set_index_register(u16::from_le_bytes([value[0], value[1]]))
+                }
+                Some((REGISTER_PC, value)) if value.len() == 2 => {
+                    cpu.set_pc(u16::from_le_bytes([value[0], value[1]]))
+                }
+                _ => return error(),
+            }
+            "OK".to_string()
+        }
+        "m" => match parse_range(arguments) {
+            Some((address, length)) => (0..length).fold(String::new(), |mut output, offset| {
+                let value = cpu.memory().get(address.wrapping_add(offset));
+                write!(output, "{:02x}", value).unwrap();
+                output
+            }),
+            None => return error(),
+        },
+        "M" => {
+            let parsed = arguments.split_once(':').and_then(|(range, data)| {
+                Some((parse_range(range)?, decode_hex(data)?))
+            });
+            match parsed {
+                Some(((address, length), data)) if data.len() == length as usize => {
+                    for (offset, value) in data.iter().enumerate() {
+                        cpu.set_memory(address.wrapping_add(offset as u16), *value);
+                    }
+                    "OK".to_string()
+                }
+                _ => return error(),
+            }
+        }
+        "c" => {
+            if let Ok(address) = u16::from_str_radix(arguments, 16) {
+                cpu.set_pc(address);
+            }
+            return Reply::Resume;
+        }
+        "s" => {
+            if let Ok(address) = u16::from_str_radix(arguments, 16) {
+                cpu.set_pc(address);
+            }
+            match debugger.step(cpu) {
+                Ok(reason) => stop_reply(reason),
+                // SIGILL
+                Err(_) => "S04".to_string(),
+            }
+        }
+        "Z" | "z" => {
+            // The kind of breakpoint, then the same fields as a memory range
+            let (kind, range) = match arguments.split_once(',') {
+                Some((kind, range)) => (Some(kind), parse_range(range)),
+                None => (None, None),
+            };
+            let insert = command == "Z";
+            match (kind, range) {
+                // Software and hardware breakpoints are the same thing here
+                (Some("0" | "1"), Some((address, _))) => {
+                    if insert {
+                        debugger.add_breakpoint(address);
+                    } else {
+                        debugger.remove_breakpoint(address);
+                    }
+                }
+                (Some("2"), Some((address, length))) => {
+                    for offset in 0..length {
+                        let watchpoint = Watchpoint::Memory(address.wrapping_add(offset));
+                        if insert {
+                            debugger.add_watchpoint(watchpoint, cpu);
+                        } else {
+                            debugger.remove_watchpoint(watchpoint);
+                        }
+                    }
+                }
+                // Read and access watchpoints are not supported
+                _ => return Reply::Packet(String::new()),
+            }
+            "OK".to_string()
+        }
+        "D" => return Reply::Detach,
+        "k" => return Reply::Kill,
+        "H" => "OK".to_string(),
+        _ => return handle_query(packet),
+    };
+    Reply::Packet(reply)
+}
+
+fn handle_query(packet: &str) -> Reply {
+    if packet == "QStartNoAckMode" {
+        return Reply::StartNoAck;
+    }
+    let reply = if packet.starts_with("qSupported") {
+        "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+".to_string()
+    } else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+        match parse_range(range) {
+            Some((offset, length)) => {
+                let start = (offset as usize).min(TARGET_XML.len());
+                let end = (start + length as usize).min(TARGET_XML.len());
+                let prefix = if end == TARGET_XML.len() { "l" } else { "m" };
+                format!("{}{}", prefix, &TARGET_XML[start..end])
+            }
+            None => return error(),
+        }
+    } else {
+        match packet {
+            "qAttached" => "1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "qC" => "QC1".to_string(),
+            // An empty reply tells GDB the packet is not supported
+            _ => String::new(),
+        }
+    };
+    Reply::Packet(reply)
+}
+
+fn error() -> Reply {
+    Reply::Packet("E01".to_string())
+}
+
+/// Formats a register in target byte order, which is little-endian for GDB's sake.
+fn read_register(cpu: &Cpu, register: usize) -> String {
+    match register {
+        REGISTER_I => encode_hex(&cpu.index_register().to_le_bytes()),
+        REGISTER_PC => encode_hex(&cpu.pc().to_le_bytes()),
+        REGISTER_SP => format!("{:02x}", cpu.call_stack().len()),
+        _ => format!("{:02x}", cpu.registers()[register]),
+    }
+}
+
+/// Parses `address,length` in hexadecimal.
+fn parse_range(range: &str) -> Option<(u16, u16)> {
+    let (address, length) = range.split_once(',')?;
+    Some((
+        u16::from_str_radix(address, 16).ok()?,
+        u16::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::Cpu;
+    use crate::cpu::Memory;
+    use crate::debugger::Debugger;
+    use crate::gdb::handle_packet;
+    use crate::gdb::Reply;
+    use crate::quirks::Quirks;
+
+    fn reply(packet: &str, cpu: &mut Cpu, debugger: &mut Debugger) -> String {
+        match handle_packet(packet, cpu, debugger) {
+            Reply::Packet(reply) => reply,
+            _ => panic!("No reply to {}", packet),
+        }
+    }
+
+    #[test]
+    fn test_registers_and_memory() {
+        let rom = vec![
+            0x60, 0x12, // v0 := 0x12
+            0xA3, 0x45, // i := 0x345
+        ];
+        let mut cpu = Cpu::new(Memory::new(rom).unwrap(), Quirks::default());
+        let mut debugger = Debugger::new();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+
+        let registers = reply("g", &mut cpu, &mut debugger);
+        assert_eq!(registers, format!("12{}4503040200", "00".repeat(15)));
+        assert_eq!(reply("p11", &mut cpu, &mut debugger), "0402");
+        assert_eq!(reply("P3=7f", &mut cpu, &mut debugger), "OK");
+        assert_eq!(cpu.registers()[3], 0x7F);
+        assert_eq!(reply("P11=0002", &mut cpu, &mut debugger), "OK");
+        assert_eq!(cpu.pc(), 0x200);
+
+        assert_eq!(reply("m200,4", &mut cpu, &mut debugger), "6012a345");
+        assert_eq!(reply("M300,2:beef", &mut cpu, &mut debugger), "OK");
+        assert_eq!(cpu.memory().get_word(0x300), 0xBEEF);
+        assert_eq!(reply("M300,2:be", &mut cpu, &mut debugger), "E01");
+    }
+
+    #[test]
+    fn test_breakpoints_and_stepping() {
+        let rom = vec![
+            0x60, 0x01, // v0 := 1
+            0xA3, 0x00, // i := 0x300
+            0xF0, 0x55, // save v0
+        ];
+        let mut cpu = Cpu::new(Memory::new(rom).unwrap(), Quirks::default());
+        let mut debugger = Debugger::new();
+        assert_eq!(reply("Z0,202,2", &mut cpu, &mut debugger), "OK");
+        assert_eq!(reply("Z2,300,1", &mut cpu, &mut debugger), "OK");
+        assert_eq!(reply("Z4,300,1", &mut cpu, &mut debugger), "");
+        assert_eq!(debugger.breakpoints().collect::<Vec<_>>(), [&0x202]);
+
+        assert_eq!(reply("s", &mut cpu, &mut debugger), "S05");
+        assert_eq!(cpu.pc(), 0x202);
+        assert_eq!(reply("s", &mut cpu, &mut debugger), "S05");
+        assert_eq!(reply("s", &mut cpu, &mut debugger), "T05watch:300;");
+
+        debugger.pause();
+        assert!(matches!(handle_packet("c", &mut cpu, &mut debugger), Reply::Resume));
+        assert_eq!(reply("z0,202,2", &mut cpu, &mut debugger), "OK");
+        assert_eq!(debugger.breakpoints().count(), 0);
+    }
+}
@@ -0,0 +1,223 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    ClearScreen,
+    ScrollDown(u8),
+    ScrollUp(u8),
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    LowResolution,
+    HighResolution,
+    Jump(u16),
+    SubroutineReturn,
+    SubroutineCall(u16),
+    SetVX {
+        register: u8,
+        value: u8,
+    },
+    AddVX {
+        register: u8,
+        value: u8,
+    },
+    SetI(u16),
+    // Loads I from the 16-bit word following the instruction (XO-CHIP F000 NNNN)
+    SetILong,
+    // An n of 0 draws a 16x16 sprite (SUPER-CHIP)
+    DisplayDraw {
+        register_x: u8,
+        register_y: u8,
+        n: u8,
+    },
+    NoopImmediateEq {
+        register: u8,
+        value: u8,
+    },
+    NoopImmediateNotEq {
+        register: u8,
+        value: u8,
+    },
+    NoopRegisterEq {
+        register_x: u8,
+        register_y: u8,
+    },
+    NoopRegisterNotEq {
+        register_x: u8,
+        register_y: u8,
+    },
+    ArithmeticSet {
+        register_x: u8,
+        register_y: u8,
+    },
+    // 8XY1 to 8XYE write their flag to VF after the result, see Cpu::set_result_and_flag
+    ArithmeticOr {
+        register_x: u8,
+        register_y: u8,
+    },
+    ArithmeticAnd {
+        register_x: u8,
+        register_y: u8,
+    },
+    ArithmeticXor {
+        register_x: u8,
+        register_y: u8,
+    },
+    ArithmeticAdd {
+        register_x: u8,
+        register_y: u8,
+    },
+    ArithmeticSubtractXY {
+        register_x: u8,
+        register_y: u8,
+    },
+    ArithmeticSubtractYX {
+        register_x: u8,
+        register_y: u8,
+    },
+    ArithmeticShiftLeft {
+        register_x: u8,
+        register_y: u8,
+    },
+    ArithmeticShiftRight {
+        register_x: u8,
+        register_y: u8,
+    },
+    Store(u8),
+    Load(u8),
+    StoreRange {
+        register_x: u8,
+        register_y: u8,
+    },
+    LoadRange {
+        register_x: u8,
+        register_y: u8,
+    },
+    BcdConversion(u8),
+    FontCharacter(u8),
+    BigFontCharacter(u8),
+    AddToIndex(u8),
+    SetVXFromDelayTimer(u8),
+    SetDelayTimerFromVX(u8),
+    SetSoundTimerFromVX(u8),
+    // Loads the 16 byte XO-CHIP audio pattern from I
+    LoadAudioPattern,
+    SetPitch(u8),
+    NoopVXDown(u8),
+    NoopVXNotDown(u8),
+    JumpWithOffset {
+        register_x: u8,
+        address: u16,
+    },
+    GetKey(u8),
+    Random {
+        register_x: u8,
+        mask: u8,
+    },
+    StoreFlags(u8),
+    LoadFlags(u8),
+    SelectPlanes(u8),
+}
+
+impl Instruction {
+    /// The opcode that decodes to this instruction. `SetILong` is followed by its operand word.
+    pub fn encode(&self) -> u16 {
+        use Instruction::*;
+        let xy = |opcode: u16, x: u8, y: u8, n: u16| opcode | (x as u16) << 8 | (y as u16) << 4 | n;
+        let x = |opcode: u16, x: u8| opcode | (x as u16) << 8;
+        match *self {
+            ClearScreen => 0x00E0,
+            ScrollDown(n) => 0x00C0 | n as u16,
+            ScrollUp(n) => 0x00D0 | n as u16,
+            ScrollRight => 0x00FB,
+            ScrollLeft => 0x00FC,
+            Exit => 0x00FD,
+            LowResolution => 0x00FE,
+            HighResolution => 0x00FF,
+            Jump(address) => 0x1000 | address,
+            SubroutineReturn => 0x00EE,
+            SubroutineCall(address) => 0x2000 | address,
+            SetVX { register, value } => x(0x6000, register) | value as u16,
+            AddVX { register, value } => x(0x7000, register) | value as u16,
+            SetI(address) => 0xA000 | address,
+            SetILong => 0xF000,
+            DisplayDraw {
+                register_x,
+                register_y,
+                n,
+            } => xy(0xD000, register_x, register_y, n as u16),
+            NoopImmediateEq { register, value } => x(0x3000, register) | value as u16,
+            NoopImmediateNotEq { register, value } => x(0x4000, register) | value as u16,
+            NoopRegisterEq {
+                register_x,
+                register_y,
+            } => xy(0x5000, register_x, register_y, 0),
+            NoopRegisterNotEq {
+                register_x,
+                register_y,
+            } => xy(0x9000, register_x, register_y, 0),
+            ArithmeticSet {
+                register_x,
+                register_y,
+            } => xy(0x8000, register_x, register_y, 0x0),
+            ArithmeticOr {
+                register_x,
+                register_y,
+            } => xy(0x8000, register_x, register_y, 0x1),
+            ArithmeticAnd {
+                register_x,
+                register_y,
+            } => xy(0x8000, register_x, register_y, 0x2),
+            ArithmeticXor {
+                register_x,
+                register_y,
+            } => xy(0x8000, register_x, register_y, 0x3),
+            ArithmeticAdd {
+                register_x,
+                register_y,
+            } => xy(0x8000, register_x, register_y, 0x4),
+            ArithmeticSubtractXY {
+                register_x,
+                register_y,
+            } => xy(0x8000, register_x, register_y, 0x5),
+            ArithmeticShiftRight {
+                register_x,
+                register_y,
+            } => xy(0x8000, register_x, register_y, 0x6),
+            ArithmeticSubtractYX {
+                register_x,
+                register_y,
+            } => xy(0x8000, register_x, register_y, 0x7),
+            ArithmeticShiftLeft {
+                register_x,
+                register_y,
+            } => xy(0x8000, register_x, register_y, 0xE),
+            Store(register) => x(0xF055, register),
+            Load(register) => x(0xF065, register),
+            StoreRange {
+                register_x,
+                register_y,
+            } => xy(0x5000, register_x, register_y, 0x2),
+            LoadRange {
+                register_x,
+                register_y,
+            } => xy(0x5000, register_x, register_y, 0x3),
+            BcdConversion(register) => x(0xF033, register),
+            FontCharacter(register) => x(0xF029, register),
+            BigFontCharacter(register) => x(0xF030, register),
+            AddToIndex(register) => x(0xF01E, register),
+            SetVXFromDelayTimer(register) => x(0xF007, register),
+            SetDelayTimerFromVX(register) => x(0xF015, register),
+            SetSoundTimerFromVX(register) => x(0xF018, register),
+            LoadAudioPattern => 0xF002,
+            SetPitch(register) => x(0xF03A, register),
+            NoopVXDown(register) => x(0xE09E, register),
+            NoopVXNotDown(register) => x(0xE0A1, register),
+            // The register of the offset jump quirk is the top nibble of the address
+            JumpWithOffset { address, .. } => 0xB000 | address,
+            GetKey(register) => x(0xF00A, register),
+            Random { register_x, mask } => x(0xC000, register_x) | mask as u16,
+            StoreFlags(register) => x(0xF075, register),
+            LoadFlags(register) => x(0xF085, register),
+            SelectPlanes(planes) => x(0xF001, planes),
+        }
+    }
+}
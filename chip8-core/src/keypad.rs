@@ -1,6 +1,9 @@
-use sdl2::keyboard::Scancode;
+use crate::error::SavestateError;
+use crate::savestate::StateReader;
+use crate::savestate::StateWriter;
 
-#[derive(Debug, Clone)]
+/// A key on the CHIP-8 hexadecimal keypad
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Keycode {
     Key0 = 0,
     Key1,
@@ -24,6 +27,12 @@ pub struct Keypad {
     down_key_map: Vec<bool>,
 }
 
+impl Default for Keypad {
+    fn default() -> Self {
+        Keypad::new()
+    }
+}
+
 impl Keypad {
     pub fn new() -> Keypad {
         Keypad {
@@ -32,7 +41,14 @@ impl Keypad {
     }
 
     pub fn require_from(raw: u32) -> Keycode {
-        match raw {
+        match Keypad::from_raw(raw) {
+            Some(keycode) => keycode,
+            None => panic!("Invalid keycode: {}", raw),
+        }
+    }
+
+    pub fn from_raw(raw: u32) -> Option<Keycode> {
+        let keycode = match raw {
             x if x == Keycode::Key0 as u32 => Keycode::Key0,
             x if x == Keycode::Key1 as u32 => Keycode::Key1,
             x if x == Keycode::Key2 as u32 => Keycode::Key2,
@@ -49,64 +65,56 @@ impl Keypad {
             x if x == Keycode::KeyD as u32 => Keycode::KeyD,
             x if x == Keycode::KeyE as u32 => Keycode::KeyE,
             x if x == Keycode::KeyF as u32 => Keycode::KeyF,
-            _ => panic!("Invalid keycode: {}", raw),
-        }
+            _ => return None,
+        };
+        Some(keycode)
     }
 
     pub fn get_first_pressed_key(&self) -> Option<Keycode> {
-        for (i, is_down) in (&self.down_key_map).into_iter().enumerate() {
+        for (i, is_down) in self.down_key_map.iter().enumerate() {
             if *is_down {
                 return Some(Keypad::require_from(i as u32));
             }
         }
-        return None;
+        None
     }
 
     pub fn is_down(&self, keycode: Keycode) -> bool {
-        return self.down_key_map[keycode as usize];
+        self.down_key_map[keycode as usize]
     }
 
-    pub fn on_down(&mut self, scancode: Scancode) {
-        match self.decode(scancode) {
-            Some(keycode) => {
-                self.down_key_map[keycode as usize] = true;
-            }
-            None => {}
-        }
+    pub fn on_down(&mut self, keycode: Keycode) {
+        self.down_key_map[keycode as usize] = true;
     }
 
-    pub fn on_up(&mut self, scancode: Scancode) {
-        match self.decode(scancode) {
-            Some(keycode) => {
-                self.down_key_map[keycode as usize] = false;
-            }
-            None => {}
-        }
+    pub fn on_up(&mut self, keycode: Keycode) {
+        self.down_key_map[keycode as usize] = false;
     }
 
-    fn decode(&self, scancode: Scancode) -> Option<Keycode> {
-        match scancode {
-            Scancode::Num1 => Some(Keycode::Key1),
-            Scancode::Num2 => Some(Keycode::Key2),
-            Scancode::Num3 => Some(Keycode::Key3),
-            Scancode::Num4 => Some(Keycode::KeyC),
-
-            Scancode::Q => Some(Keycode::Key4),
-            Scancode::W => Some(Keycode::Key5),
-            Scancode::E => Some(Keycode::Key6),
-            Scancode::R => Some(Keycode::KeyD),
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.down_mask());
+    }
 
-            Scancode::A => Some(Keycode::Key7),
-            Scancode::S => Some(Keycode::Key8),
-            Scancode::D => Some(Keycode::Key9),
-            Scancode::F => Some(Keycode::KeyE),
+    pub(crate) fn load_state(reader: &mut StateReader) -> Result<Keypad, SavestateError> {
+        let mut keypad = Keypad::new();
+        keypad.set_down_mask(reader.read_u16()?);
+        Ok(keypad)
+    }
 
-            Scancode::Z => Some(Keycode::KeyA),
-            Scancode::X => Some(Keycode::Key0),
-            Scancode::C => Some(Keycode::KeyB),
-            Scancode::V => Some(Keycode::KeyF),
+    /// The pressed keys, with bit N set if key N is down
+    pub fn down_mask(&self) -> u16 {
+        let mut mask = 0;
+        for (i, is_down) in self.down_key_map.iter().enumerate() {
+            if *is_down {
+                mask |= 1 << i;
+            }
+        }
+        mask
+    }
 
-            _ => None,
+    pub fn set_down_mask(&mut self, mask: u16) {
+        for (i, is_down) in self.down_key_map.iter_mut().enumerate() {
+            *is_down = mask & (1 << i) != 0;
         }
     }
 }
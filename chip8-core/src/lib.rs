@@ -0,0 +1,17 @@
+pub mod asm;
+pub mod compat;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod display;
+pub mod dump;
+pub mod error;
+pub mod gdb;
+pub mod instruction;
+pub mod keypad;
+pub mod movie;
+pub mod octo;
+pub mod quirks;
+pub mod rewind;
+pub mod savestate;
+pub mod trace;
@@ -0,0 +1,195 @@
+use crate::cpu::Cpu;
+use crate::cpu::Memory;
+use crate::error::SavestateError;
+use crate::quirks::Quirks;
+use crate::savestate::read_quirks;
+use crate::savestate::write_quirks;
+use crate::savestate::StateReader;
+use crate::savestate::StateWriter;
+
+const MAGIC: &[u8; 4] = b"C8MV";
+// Bump whenever the movie layout changes
+const VERSION: u8 = 2;
+
+/// Keypad input of a session, recorded once per 60 Hz frame, along with everything else needed to
+/// reproduce the session from power-on.
+///
+/// Each frame first applies its keypad state, then runs up to `instructions_in_frame` instructions,
+/// stopping early while the CPU waits for vblank or has exited, and finally ticks the timers.
+/// Frontends must step the CPU this way for a recording to replay identically.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub seed: u64,
+    pub quirks: Quirks,
+    pub instructions_per_second: u32,
+    rom_checksum: u64,
+    frames: Vec<u16>,
+}
+
+impl Movie {
+    /// Starts an empty recording of `rom` running with the given settings.
+    pub fn new(rom: &[u8], seed: u64, quirks: Quirks, instructions_per_second: u32) -> Movie {
+        Movie {
+            seed,
+            quirks,
+            instructions_per_second,
+            rom_checksum: checksum(rom),
+            frames: vec![],
+        }
+    }
+
+    /// Number of recorded frames.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Keypad down mask of a frame, see `Keypad::down_mask`.
+    pub fn frame(&self, index: usize) -> Option<u16> {
+        self.frames.get(index).copied()
+    }
+
+    pub fn record_frame(&mut self, down_mask: u16) {
+        self.frames.push(down_mask);
+    }
+
+    /// Whether the movie was recorded with this ROM.
+    pub fn matches_rom(&self, rom: &[u8]) -> bool {
+        self.rom_checksum == checksum(rom)
+    }
+
+    /// Creates the machine the recording started from.
+    pub fn start(&self, rom: &[u8]) -> Result<Cpu, String> {
+        Ok(Cpu::with_seed(
+            Memory::new(rom.to_vec())?,
+            self.quirks,
+            self.seed,
+        ))
+    }
+
+    /// Replays every frame from power-on, stopping at the first decode error.
+    pub fn replay(&self, rom: &[u8]) -> Result<Cpu, String> {
+        let mut cpu = self.start(rom)?;
+        for (frame, down_mask) in self.frames.iter().enumerate() {
+            cpu.keypad().set_down_mask(*down_mask);
+            for _ in 0..instructions_in_frame(self.instructions_per_second, frame as u64) {
+                if cpu.is_waiting_for_vblank() || cpu.has_exited() {
+                    break;
+                }
+                cpu.step().map_err(|e| format!("Frame {}: {}", frame, e))?;
+            }
+            cpu.tick_timers();
+        }
+        Ok(cpu)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new(MAGIC, VERSION);
+        writer.write_u64(self.rom_checksum);
+        writer.write_u64(self.seed);
+        write_quirks(&mut writer, &self.quirks);
+        writer.write_u32(self.instructions_per_second);
+        writer.write_u32(self.frames.len() as u32);
+        for down_mask in &self.frames {
+            writer.write_u16(*down_mask);
+        }
+        writer.into_data()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, SavestateError> {
+        let mut reader = StateReader::new(data, MAGIC, VERSION)?;
+        let rom_checksum = reader.read_u64()?;
+        let seed = reader.read_u64()?;
+        let quirks = read_quirks(&mut reader)?;
+        let instructions_per_second = reader.read_u32()?;
+        let frame_count = reader.read_u32()?;
+        let frames = (0..frame_count)
+            .map(|_| reader.read_u16())
+            .collect::<Result<Vec<u16>, SavestateError>>()?;
+        reader.finish()?;
+        Ok(Movie {
+            seed,
+            quirks,
+            instructions_per_second,
+            rom_checksum,
+            frames,
+        })
+    }
+}
+
+/// Number of instructions to run in a 60 Hz frame, counting from 0, so that every second runs
+/// exactly `instructions_per_second` instructions. Frames that would run a fraction of an
+/// instruction carry it over, so 700 instructions per second run 11 or 12 per frame.
+pub fn instructions_in_frame(instructions_per_second: u32, frame: u64) -> u32 {
+    let per_second = instructions_per_second as u64;
+    ((frame + 1) * per_second / 60 - frame * per_second / 60) as u32
+}
+
+// 64-bit FNV-1a, enough to tell ROMs and screens apart
+pub(crate) fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF29CE484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001B3)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::SavestateError;
+    use crate::movie::instructions_in_frame;
+    use crate::movie::Movie;
+    use crate::quirks::Platform;
+
+    const ROM: [u8; 10] = [
+        0x60, 0x05, // v0 := 5
+        0xE0, 0xA1, // if v0 key then
+        0x71, 0x01, // v1 += 1
+        0xC2, 0xFF, // v2 := random 0xFF
+        0x12, 0x02, // jump 0x202
+    ];
+
+    #[test]
+    fn test_replay() {
+        // 4 instructions per frame
+        let mut movie = Movie::new(&ROM, 42, Platform::Chip48.quirks(), 240);
+        for down_mask in [0x0000, 0x0020, 0x0020, 0x0001] {
+            movie.record_frame(down_mask);
+        }
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        assert_eq!(movie.len(), 4);
+        assert!(movie.matches_rom(&ROM));
+        assert!(!movie.matches_rom(&ROM[..8]));
+
+        let mut cpu = movie.replay(&ROM).unwrap();
+        assert_eq!(cpu.registers()[1], 2);
+        assert_eq!(cpu.keypad().down_mask(), 0x0001);
+        // Replaying again reproduces the random numbers too
+        assert_eq!(movie.replay(&ROM).unwrap().registers(), cpu.registers());
+    }
+
+    #[test]
+    fn test_invalid_movie() {
+        assert_eq!(
+            Movie::from_bytes(b"C8SS\x01").err(),
+            Some(SavestateError::BadMagic)
+        );
+        let mut data = Movie::new(&ROM, 0, Platform::Modern.quirks(), 700).to_bytes();
+        data.push(0);
+        assert!(matches!(
+            Movie::from_bytes(&data),
+            Err(SavestateError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_instructions_in_frame() {
+        let frames: Vec<u32> = (0..60).map(|frame| instructions_in_frame(700, frame)).collect();
+        assert_eq!(frames.iter().sum::<u32>(), 700);
+        assert!(frames.iter().all(|count| *count == 11 || *count == 12));
+        assert_eq!((60..120).map(|frame| instructions_in_frame(700, frame)).sum::<u32>(), 700);
+        assert_eq!((0..60).map(|frame| instructions_in_frame(30, frame)).sum::<u32>(), 30);
+        assert!((0..60).all(|frame| instructions_in_frame(600, frame) == 10));
+    }
+}
@@ -0,0 +1,1032 @@
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+
+use crate::cpu::START_ADDRESS;
+use crate::error::AsmError;
+use crate::instruction::Instruction;
+
+// Stops macros that expand into themselves
+const MAX_EXPANDED_TOKENS: usize = 1_000_000;
+
+#[derive(Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    arguments: Vec<String>,
+    body: Vec<Token>,
+}
+
+enum Fixup {
+    // The low 12 bits of an instruction
+    Address,
+    // The word following `i := long`
+    Long,
+    // The two `vX := NN` instructions of `:unpack`, with the nibble for the first one
+    Unpack(u8),
+}
+
+/// A use of a label before its definition, patched once the program is compiled.
+struct Reference {
+    address: u16,
+    name: String,
+    line: usize,
+    fixup: Fixup,
+}
+
+enum Block {
+    // `if ... begin`, with the jump over the block
+    If(u16),
+    // `else`, with the jump over the else branch
+    Else(u16),
+    // `loop`, with its start and the jumps out of every `while`
+    Loop(u16, Vec<u16>),
+}
+
+struct Compiler {
+    file: String,
+    tokens: VecDeque<Token>,
+    line: usize,
+    expanded_tokens: usize,
+    // Bytes from START_ADDRESS on, with None for gaps left by `:org`
+    rom: Vec<Option<u8>>,
+    // Wider than an address to detect programs that do not fit in memory
+    position: u32,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    references: Vec<Reference>,
+    blocks: Vec<Block>,
+}
+
+/// Compiles an Octo program into ROM bytes loaded at 0x200.
+///
+/// Supports the Octo statements for every instruction, labels (`: name`), `:const`, `:alias`,
+/// `:calc`, `:org`, `:macro`, `:call`, `:byte`, `:unpack` and the structured `if ... then`,
+/// `if ... begin ... else ... end` and `loop ... while ... again` forms. As in Octo, the program
+/// starts with a jump to the `main` label.
+pub fn compile(source: &str) -> Result<Vec<u8>, AsmError> {
+    compile_named(source, "<source>")
+}
+
+/// Compiles an Octo source file.
+pub fn compile_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let file = path.display().to_string();
+    let source = fs::read_to_string(path).map_err(|e| AsmError {
+        file: file.clone(),
+        line: 0,
+        message: e.to_string(),
+    })?;
+    compile_named(&source, &file)
+}
+
+fn compile_named(source: &str, file: &str) -> Result<Vec<u8>, AsmError> {
+    let mut tokens = VecDeque::new();
+    for (index, line) in source.lines().enumerate() {
+        let line_tokens = line
+            .split('#')
+            .next()
+            .unwrap()
+            .split_whitespace()
+            .map(|text| Token {
+                text: text.to_string(),
+                line: index + 1,
+            });
+        tokens.extend(line_tokens);
+    }
+    let mut compiler = Compiler {
+        file: file.to_string(),
+        tokens,
+        line: 0,
+        expanded_tokens: 0,
+        rom: vec![],
+        position: START_ADDRESS as u32,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        references: vec![],
+        blocks: vec![],
+    };
+    compiler.compile().map_err(|message| AsmError {
+        file: compiler.file.clone(),
+        line: compiler.line,
+        message,
+    })
+}
+
+impl Compiler {
+    fn compile(&mut self) -> Result<Vec<u8>, String> {
+        self.reference("main", Fixup::Address)?;
+        self.instruction(Instruction::Jump(0))?;
+        while let Some(token) = self.next_token() {
+            self.statement(&token)?;
+        }
+        if let Some(block) = self.blocks.last() {
+            return Err(match block {
+                Block::If(_) | Block::Else(_) => "Missing 'end'".to_string(),
+                Block::Loop(..) => "Missing 'again'".to_string(),
+            });
+        }
+
+        for reference in std::mem::take(&mut self.references) {
+            self.line = reference.line;
+            let target = match self.labels.get(&reference.name) {
+                Some(target) => *target,
+                None if reference.name == "main" => return Err("Missing ': main'".to_string()),
+                None => return Err(format!("Undefined name '{}'", reference.name)),
+            };
+            match reference.fixup {
+                Fixup::Address => {
+                    check_address(target)?;
+                    self.patch_word(reference.address, |word| word | target);
+                }
+                Fixup::Long => self.patch_word(reference.address, |_| target),
+                Fixup::Unpack(nibble) => {
+                    check_address(target)?;
+                    let high = (nibble << 4) as u16 | target >> 8;
+                    self.patch_word(reference.address, |word| word | high);
+                    self.patch_word(reference.address + 2, |word| word | (target & 0xFF));
+                }
+            }
+        }
+        Ok(self.rom.iter().map(|byte| byte.unwrap_or(0)).collect())
+    }
+
+    fn next_token(&mut self) -> Option<Token> {
+        let token = self.tokens.pop_front()?;
+        self.line = token.line;
+        Some(token)
+    }
+
+    fn expect_token(&mut self) -> Result<String, String> {
+        self.next_token()
+            .map(|token| token.text)
+            .ok_or_else(|| "Unexpected end of file".to_string())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.expect_token()?;
+        if token != expected {
+            return Err(format!("Expected '{}', found '{}'", expected, token));
+        }
+        Ok(())
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.front().is_some_and(|token| token.text == text)
+    }
+
+    /// The tokens up to the `}` matching an already consumed `{`.
+    fn braced_tokens(&mut self) -> Result<Vec<Token>, String> {
+        let mut tokens = vec![];
+        let mut depth = 0;
+        loop {
+            let token = self
+                .next_token()
+                .ok_or_else(|| "Missing '}'".to_string())?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(tokens),
+                "}" => depth -= 1,
+                _ => {}
+            }
+            tokens.push(token);
+        }
+    }
+
+    fn statement(&mut self, token: &Token) -> Result<(), String> {
+        use Instruction::*;
+        match token.text.as_str() {
+            ":" => {
+                let name = self.new_name()?;
+                self.labels.insert(name, self.address()?);
+            }
+            ":const" => {
+                let name = self.new_name()?;
+                let value = self.expect_token()?;
+                let value = self.value(&value)?;
+                self.constants.insert(name, value as f64);
+            }
+            ":alias" => {
+                let name = self.new_name()?;
+                let register = self.register_token()?;
+                self.aliases.insert(name, register);
+            }
+            ":calc" => {
+                let name = self.new_name()?;
+                self.expect("{")?;
+                let expression: Vec<String> =
+                    self.braced_tokens()?.into_iter().map(|token| token.text).collect();
+                let mut position = 0;
+                let value = self.calculate(&expression, &mut position)?;
+                if position != expression.len() {
+                    return Err(format!("Unexpected '{}' in :calc", expression[position]));
+                }
+                self.constants.insert(name, value);
+            }
+            ":org" => {
+                let address = self.expect_token()?;
+                let address = self.value(&address)?;
+                if !(START_ADDRESS as i64..=0xFFFF).contains(&address) {
+                    return Err(format!(":org address {:#X} is outside of the program", address));
+                }
+                self.position = address as u32;
+            }
+            ":macro" => {
+                let name = self.new_name()?;
+                let mut arguments = vec![];
+                loop {
+                    let argument = self.expect_token()?;
+                    if argument == "{" {
+                        break;
+                    }
+                    arguments.push(argument);
+                }
+                let body = self.braced_tokens()?;
+                self.macros.insert(name, Macro { arguments, body });
+            }
+            ":call" => {
+                let target = self.expect_token()?;
+                let address = self.address_operand(&target, Fixup::Address)?;
+                self.instruction(SubroutineCall(address))?;
+            }
+            ":byte" => {
+                let value = self.expect_token()?;
+                let value = self.byte(&value)?;
+                self.emit(value)?;
+            }
+            ":unpack" => {
+                let nibble = self.expect_token()?;
+                let nibble = self.nibble(&nibble)?;
+                let target = self.expect_token()?;
+                let address = self.address_operand(&target, Fixup::Unpack(nibble))?;
+                let value = ((nibble as u16) << 4 | address >> 8) as u8;
+                self.instruction(SetVX { register: 0, value })?;
+                self.instruction(SetVX {
+                    register: 1,
+                    value: address as u8,
+                })?;
+            }
+            // Breakpoints only matter to Octo's debugger
+            ":breakpoint" => {
+                self.expect_token()?;
+            }
+            "return" | ";" => self.instruction(SubroutineReturn)?,
+            "clear" => self.instruction(ClearScreen)?,
+            "hires" => self.instruction(HighResolution)?,
+            "lores" => self.instruction(LowResolution)?,
+            "exit" => self.instruction(Exit)?,
+            "scroll-down" => {
+                let n = self.nibble_token()?;
+                self.instruction(ScrollDown(n))?;
+            }
+            "scroll-up" => {
+                let n = self.nibble_token()?;
+                self.instruction(ScrollUp(n))?;
+            }
+            "scroll-right" => self.instruction(ScrollRight)?,
+            "scroll-left" => self.instruction(ScrollLeft)?,
+            "audio" => self.instruction(LoadAudioPattern)?,
+            "plane" => {
+                let planes = self.nibble_token()?;
+                self.instruction(SelectPlanes(planes))?;
+            }
+            "jump" => {
+                let target = self.expect_token()?;
+                let address = self.address_operand(&target, Fixup::Address)?;
+                self.instruction(Jump(address))?;
+            }
+            "jump0" => {
+                let target = self.expect_token()?;
+                let address = self.address_operand(&target, Fixup::Address)?;
+                self.instruction(JumpWithOffset {
+                    register_x: (address >> 8) as u8,
+                    address,
+                })?;
+            }
+            "sprite" => {
+                let register_x = self.register_token()?;
+                let register_y = self.register_token()?;
+                let n = self.nibble_token()?;
+                self.instruction(DisplayDraw {
+                    register_x,
+                    register_y,
+                    n,
+                })?;
+            }
+            "bcd" => {
+                let register = self.register_token()?;
+                self.instruction(BcdConversion(register))?;
+            }
+            "save" | "load" => {
+                let register_x = self.register_token()?;
+                let is_save = token.text == "save";
+                let instruction = if self.peek_is("-") {
+                    self.next_token();
+                    let register_y = self.register_token()?;
+                    if is_save {
+                        StoreRange {
+                            register_x,
+                            register_y,
+                        }
+                    } else {
+                        LoadRange {
+                            register_x,
+                            register_y,
+                        }
+                    }
+                } else if is_save {
+                    Store(register_x)
+                } else {
+                    Load(register_x)
+                };
+                self.instruction(instruction)?;
+            }
+            "saveflags" => {
+                let register = self.register_token()?;
+                self.instruction(StoreFlags(register))?;
+            }
+            "loadflags" => {
+                let register = self.register_token()?;
+                self.instruction(LoadFlags(register))?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let register = self.register_token()?;
+                self.instruction(match token.text.as_str() {
+                    "delay" => SetDelayTimerFromVX(register),
+                    "buzzer" => SetSoundTimerFromVX(register),
+                    _ => SetPitch(register),
+                })?;
+            }
+            "i" => self.index_statement()?,
+            "if" => {
+                let (skip_if_true, skip_if_false) = self.condition()?;
+                match self.expect_token()?.as_str() {
+                    "then" => self.instruction(skip_if_false)?,
+                    "begin" => {
+                        self.instruction(skip_if_true)?;
+                        let jump = self.address()?;
+                        self.instruction(Jump(0))?;
+                        self.blocks.push(Block::If(jump));
+                    }
+                    other => return Err(format!("Expected 'then' or 'begin', found '{}'", other)),
+                }
+            }
+            "else" => {
+                let skip = match self.blocks.pop() {
+                    Some(Block::If(jump)) => jump,
+                    _ => return Err("'else' without 'if ... begin'".to_string()),
+                };
+                let jump = self.address()?;
+                self.instruction(Jump(0))?;
+                self.patch_jump(skip)?;
+                self.blocks.push(Block::Else(jump));
+            }
+            "end" => match self.blocks.pop() {
+                Some(Block::If(jump) | Block::Else(jump)) => self.patch_jump(jump)?,
+                _ => return Err("'end' without 'if ... begin'".to_string()),
+            },
+            "loop" => {
+                let start = self.address()?;
+                self.blocks.push(Block::Loop(start, vec![]));
+            }
+            "while" => {
+                let (skip_if_true, _) = self.condition()?;
+                self.instruction(skip_if_true)?;
+                let jump = self.address()?;
+                self.instruction(Jump(0))?;
+                let breaks = self.blocks.iter_mut().rev().find_map(|block| match block {
+                    Block::Loop(_, breaks) => Some(breaks),
+                    _ => None,
+                });
+                match breaks {
+                    Some(breaks) => breaks.push(jump),
+                    None => return Err("'while' outside of a loop".to_string()),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop(start, breaks)) => {
+                    self.instruction(Jump(start))?;
+                    for jump in breaks {
+                        self.patch_jump(jump)?;
+                    }
+                }
+                _ => return Err("'again' without 'loop'".to_string()),
+            },
+            text => {
+                if let Some(register) = self.register(text) {
+                    return self.register_statement(register);
+                }
+                if let Some(expansion) = self.macros.get(text) {
+                    let arguments = expansion.arguments.clone();
+                    let mut body = expansion.body.clone();
+                    let mut values = HashMap::new();
+                    for argument in arguments {
+                        values.insert(argument, self.expect_token()?);
+                    }
+                    for body_token in &mut body {
+                        if let Some(value) = values.get(&body_token.text) {
+                            body_token.text = value.clone();
+                        }
+                        // Errors inside the expansion point at the use of the macro
+                        body_token.line = token.line;
+                    }
+                    self.expanded_tokens += body.len();
+                    if self.expanded_tokens > MAX_EXPANDED_TOKENS {
+                        return Err(format!("Macro '{}' expands without end", text));
+                    }
+                    for body_token in body.into_iter().rev() {
+                        self.tokens.push_front(body_token);
+                    }
+                    return Ok(());
+                }
+                if let Some(value) = parse_number(text) {
+                    let value = byte_in_range(value)?;
+                    return self.emit(value);
+                }
+                if is_name(text) {
+                    // A bare label calls it
+                    let address = self.address_operand(text, Fixup::Address)?;
+                    return self.instruction(SubroutineCall(address));
+                }
+                return Err(format!("Unexpected '{}'", text));
+            }
+        }
+        Ok(())
+    }
+
+    fn index_statement(&mut self) -> Result<(), String> {
+        use Instruction::*;
+        match self.expect_token()?.as_str() {
+            ":=" => {
+                let operand = self.expect_token()?;
+                match operand.as_str() {
+                    "long" => {
+                        let target = self.expect_token()?;
+                        let address = self.address_operand(&target, Fixup::Long)?;
+                        self.instruction(SetILong)?;
+                        self.emit_word(address)?;
+                    }
+                    "hex" => {
+                        let register = self.register_token()?;
+                        self.instruction(FontCharacter(register))?;
+                    }
+                    "bighex" => {
+                        let register = self.register_token()?;
+                        self.instruction(BigFontCharacter(register))?;
+                    }
+                    _ => {
+                        let address = self.address_operand(&operand, Fixup::Address)?;
+                        self.instruction(SetI(address))?;
+                    }
+                }
+            }
+            "+=" => {
+                let register = self.register_token()?;
+                self.instruction(AddToIndex(register))?;
+            }
+            other => return Err(format!("Unknown operator 'i {}'", other)),
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, register_x: u8) -> Result<(), String> {
+        use Instruction::*;
+        let operator = self.expect_token()?;
+        let operand = self.expect_token()?;
+        let register_y = self.register(&operand);
+        let instruction = match (operator.as_str(), register_y) {
+            (":=", _) if operand == "random" => {
+                let mask = self.expect_token()?;
+                Random {
+                    register_x,
+                    mask: self.byte(&mask)?,
+                }
+            }
+            (":=", _) if operand == "key" => GetKey(register_x),
+            (":=", _) if operand == "delay" => SetVXFromDelayTimer(register_x),
+            (":=", Some(register_y)) => ArithmeticSet {
+                register_x,
+                register_y,
+            },
+            (":=", None) => SetVX {
+                register: register_x,
+                value: self.byte(&operand)?,
+            },
+            ("+=", Some(register_y)) => ArithmeticAdd {
+                register_x,
+                register_y,
+            },
+            ("+=", None) => AddVX {
+                register: register_x,
+                value: self.byte(&operand)?,
+            },
+            ("-=", Some(register_y)) => ArithmeticSubtractXY {
+                register_x,
+                register_y,
+            },
+            ("-=", None) => AddVX {
+                register: register_x,
+                value: self.byte(&operand)?.wrapping_neg(),
+            },
+            ("=-", Some(register_y)) => ArithmeticSubtractYX {
+                register_x,
+                register_y,
+            },
+            ("|=", Some(register_y)) => ArithmeticOr {
+                register_x,
+                register_y,
+            },
+            ("&=", Some(register_y)) => ArithmeticAnd {
+                register_x,
+                register_y,
+            },
+            ("^=", Some(register_y)) => ArithmeticXor {
+                register_x,
+                register_y,
+            },
+            (">>=", Some(register_y)) => ArithmeticShiftRight {
+                register_x,
+                register_y,
+            },
+            ("<<=", Some(register_y)) => ArithmeticShiftLeft {
+                register_x,
+                register_y,
+            },
+            _ => return Err(format!("Unknown operation 'v{:x} {} {}'", register_x, operator, operand)),
+        };
+        self.instruction(instruction)
+    }
+
+    /// Parses a condition into the skips that skip the next instruction when it holds and when
+    /// it does not.
+    fn condition(&mut self) -> Result<(Instruction, Instruction), String> {
+        use Instruction::*;
+        let register_x = self.register_token()?;
+        let operator = self.expect_token()?;
+        let (equal, not_equal) = match operator.as_str() {
+            "key" => return Ok((NoopVXDown(register_x), NoopVXNotDown(register_x))),
+            "-key" => return Ok((NoopVXNotDown(register_x), NoopVXDown(register_x))),
+            "==" | "!=" => {
+                let operand = self.expect_token()?;
+                match self.register(&operand) {
+                    Some(register_y) => (
+                        NoopRegisterEq {
+                            register_x,
+                            register_y,
+                        },
+                        NoopRegisterNotEq {
+                            register_x,
+                            register_y,
+                        },
+                    ),
+                    None => {
+                        let value = self.byte(&operand)?;
+                        (
+                            NoopImmediateEq {
+                                register: register_x,
+                                value,
+                            },
+                            NoopImmediateNotEq {
+                                register: register_x,
+                                value,
+                            },
+                        )
+                    }
+                }
+            }
+            other => return Err(format!("Unsupported comparison '{}'", other)),
+        };
+        if operator == "==" {
+            Ok((equal, not_equal))
+        } else {
+            Ok((not_equal, equal))
+        }
+    }
+
+    /// Evaluates a `:calc` expression. Like Octo, operators have no precedence and evaluate
+    /// from right to left.
+    fn calculate(&self, tokens: &[String], position: &mut usize) -> Result<f64, String> {
+        let left = self.calculate_term(tokens, position)?;
+        let operator = match tokens.get(*position) {
+            Some(operator) if operator != ")" => operator.as_str(),
+            _ => return Ok(left),
+        };
+        *position += 1;
+        let right = self.calculate(tokens, position)?;
+        let boolean = |value: bool| if value { 1.0 } else { 0.0 };
+        Ok(match operator {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => ((left as i64) & (right as i64)) as f64,
+            "|" => ((left as i64) | (right as i64)) as f64,
+            "^" => ((left as i64) ^ (right as i64)) as f64,
+            "<<" | ">>" => {
+                let value = u32::try_from(right as i64)
+                    .ok()
+                    .and_then(|shift| match operator {
+                        "<<" => (left as i64).checked_shl(shift),
+                        _ => (left as i64).checked_shr(shift),
+                    })
+                    .ok_or_else(|| format!("Invalid shift by {} in :calc", right))?;
+                value as f64
+            }
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => boolean(left < right),
+            ">" => boolean(left > right),
+            "<=" => boolean(left <= right),
+            ">=" => boolean(left >= right),
+            "==" => boolean(left == right),
+            "!=" => boolean(left != right),
+            other => return Err(format!("Unknown operator '{}' in :calc", other)),
+        })
+    }
+
+    fn calculate_term(&self, tokens: &[String], position: &mut usize) -> Result<f64, String> {
+        let token = tokens
+            .get(*position)
+            .ok_or_else(|| "Incomplete :calc expression".to_string())?;
+        *position += 1;
+        match token.as_str() {
+            "(" => {
+                let value = self.calculate(tokens, position)?;
+                if tokens.get(*position).map(|token| token.as_str()) != Some(")") {
+                    return Err("Missing ')' in :calc".to_string());
+                }
+                *position += 1;
+                Ok(value)
+            }
+            "-" => Ok(-self.calculate_term(tokens, position)?),
+            "~" => Ok(!(self.calculate_term(tokens, position)? as i64) as f64),
+            "!" => Ok(if self.calculate_term(tokens, position)? == 0.0 { 1.0 } else { 0.0 }),
+            "HERE" => Ok(self.position as f64),
+            name => match self.constants.get(name) {
+                Some(value) => Ok(*value),
+                None => self.value(name).map(|value| value as f64),
+            },
+        }
+    }
+
+    /// The value of a number, constant or already defined label.
+    fn value(&self, text: &str) -> Result<i64, String> {
+        if let Some(value) = parse_number(text) {
+            return Ok(value);
+        }
+        if let Some(value) = self.constants.get(text) {
+            return Ok(value.floor() as i64);
+        }
+        if let Some(address) = self.labels.get(text) {
+            return Ok(*address as i64);
+        }
+        Err(format!("Undefined name '{}'", text))
+    }
+
+    fn byte(&self, text: &str) -> Result<u8, String> {
+        byte_in_range(self.value(text)?)
+    }
+
+    fn nibble(&self, text: &str) -> Result<u8, String> {
+        let value = self.value(text)?;
+        if !(0..=0xF).contains(&value) {
+            return Err(format!("{} does not fit in a nibble", value));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble_token(&mut self) -> Result<u8, String> {
+        let token = self.expect_token()?;
+        self.nibble(&token)
+    }
+
+    /// The value of an address operand. Labels that are not defined yet resolve to 0 and are
+    /// patched at the end.
+    fn address_operand(&mut self, text: &str, fixup: Fixup) -> Result<u16, String> {
+        match self.value(text) {
+            Ok(value) => {
+                let max = if let Fixup::Long = fixup { 0xFFFF } else { 0xFFF };
+                if !(0..=max).contains(&value) {
+                    return Err(format!("Address {:#X} is out of range", value));
+                }
+                Ok(value as u16)
+            }
+            Err(_) if is_name(text) && self.register(text).is_none() => {
+                self.reference(text, fixup)?;
+                Ok(0)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn reference(&mut self, name: &str, fixup: Fixup) -> Result<(), String> {
+        // `i := long` patches the word after the instruction
+        let offset = if let Fixup::Long = fixup { 2 } else { 0 };
+        let address = self
+            .address()?
+            .checked_add(offset)
+            .ok_or_else(|| "Program does not fit in memory".to_string())?;
+        self.references.push(Reference {
+            address,
+            name: name.to_string(),
+            line: self.line,
+            fixup,
+        });
+        Ok(())
+    }
+
+    fn register(&self, text: &str) -> Option<u8> {
+        if let Some(register) = self.aliases.get(text) {
+            return Some(*register);
+        }
+        let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn register_token(&mut self) -> Result<u8, String> {
+        let token = self.expect_token()?;
+        self.register(&token)
+            .ok_or_else(|| format!("Expected a register, found '{}'", token))
+    }
+
+    /// Reads the name of a new label, constant, alias or macro.
+    fn new_name(&mut self) -> Result<String, String> {
+        let name = self.expect_token()?;
+        if !is_name(&name) || self.register(&name).is_some() {
+            return Err(format!("Invalid name '{}'", name));
+        }
+        if self.labels.contains_key(&name)
+            || self.constants.contains_key(&name)
+            || self.aliases.contains_key(&name)
+            || self.macros.contains_key(&name)
+        {
+            return Err(format!("'{}' is defined twice", name));
+        }
+        Ok(name)
+    }
+
+    /// The address the next byte is written to.
+    fn address(&self) -> Result<u16, String> {
+        if self.position > 0xFFFF {
+            return Err("Program does not fit in memory".to_string());
+        }
+        Ok(self.position as u16)
+    }
+
+    fn emit(&mut self, byte: u8) -> Result<(), String> {
+        let address = self.address()?;
+        let index = (address - START_ADDRESS) as usize;
+        if index >= self.rom.len() {
+            self.rom.resize(index + 1, None);
+        }
+        if self.rom[index].is_some() {
+            return Err(format!("Code overlaps at {:#05X}", address));
+        }
+        self.rom[index] = Some(byte);
+        self.position += 1;
+        Ok(())
+    }
+
+    fn emit_word(&mut self, word: u16) -> Result<(), String> {
+        let [high, low] = word.to_be_bytes();
+        self.emit(high)?;
+        self.emit(low)
+    }
+
+    fn instruction(&mut self, instruction: Instruction) -> Result<(), String> {
+        self.emit_word(instruction.encode())
+    }
+
+    fn patch_word(&mut self, address: u16, patch: impl Fn(u16) -> u16) {
+        let index = (address - START_ADDRESS) as usize;
+        let word = u16::from_be_bytes([self.rom[index].unwrap(), self.rom[index + 1].unwrap()]);
+        let [high, low] = patch(word).to_be_bytes();
+        self.rom[index] = Some(high);
+        self.rom[index + 1] = Some(low);
+    }
+
+    /// Points the placeholder jump at `address` to the current position.
+    fn patch_jump(&mut self, address: u16) -> Result<(), String> {
+        let target = self.address()?;
+        check_address(target)?;
+        self.patch_word(address, |_| Instruction::Jump(target).encode());
+        Ok(())
+    }
+}
+
+fn check_address(address: u16) -> Result<(), String> {
+    if address > 0xFFF {
+        return Err(format!("Address {:#X} is out of range", address));
+    }
+    Ok(())
+}
+
+fn byte_in_range(value: i64) -> Result<u8, String> {
+    if !(-0x80..=0xFF).contains(&value) {
+        return Err(format!("{} does not fit in a byte", value));
+    }
+    Ok(value as u8)
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b").or(digits.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn is_name(text: &str) -> bool {
+    let mut characters = text.chars();
+    characters
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && characters.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::Cpu;
+    use crate::cpu::Memory;
+    use crate::disasm;
+    use crate::disasm::Syntax;
+    use crate::error::AsmError;
+    use crate::octo::compile;
+    use crate::quirks::Quirks;
+
+    fn listing(rom: &[u8]) -> Vec<String> {
+        disasm::disassemble(rom, Syntax::Octo)
+            .into_iter()
+            .map(|line| line.text)
+            .collect()
+    }
+
+    #[test]
+    fn test_statements() {
+        let source = "
+            # Draws the sprite below at the position in the constants
+            :const X 10
+            :alias y v1
+            : main
+                v0 := X
+                y := 0x20
+                y += -1
+                v0 -= y
+                i := sprite
+                sprite v0 y 2
+                i := long sprite
+                delay := v0
+            : sprite
+                0b10000001 0xFF
+        ";
+        assert_eq!(
+            listing(&compile(source).unwrap()),
+            [
+                "jump 0x202",
+                "v0 := 0x0A",
+                "v1 := 0x20",
+                "v1 += 0xFF",
+                "v0 -= v1",
+                "i := 0x214",
+                "sprite v0 v1 2",
+                "i := long 0x0214",
+                "delay := v0",
+                "0x81 0xFF",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let source = "
+            : main
+                v0 := 0
+                loop
+                    v0 += 1
+                    if v0 == 3 then v1 := 1
+                    if v0 != 4 begin
+                        v2 += 1
+                    else
+                        v3 := 1
+                    end
+                    while v0 != 5
+                again
+                count
+                exit
+            : count
+                v4 += 1
+                return
+        ";
+        let rom = compile(source).unwrap();
+        assert_eq!(
+            listing(&rom),
+            [
+                "jump 0x202",
+                "v0 := 0x00",
+                "v0 += 0x01",
+                "if v0 == 0x03 then",
+                "v1 := 0x01",
+                "if v0 == 0x04 then",
+                "jump 0x212",
+                "v2 += 0x01",
+                "jump 0x214",
+                "v3 := 0x01",
+                "if v0 == 0x05 then",
+                "jump 0x21A",
+                "jump 0x204",
+                ":call 0x21E",
+                "exit",
+                "v4 += 0x01",
+                "return",
+            ]
+        );
+
+        let mut cpu = Cpu::new(Memory::new(rom).unwrap(), Quirks::default());
+        while !cpu.has_exited() {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.registers()[..5], [5, 1, 4, 1, 1]);
+    }
+
+    #[test]
+    fn test_macros_and_calc() {
+        let source = "
+            :macro add-twice register amount {
+                register += amount
+                register += amount
+            }
+            :calc DOUBLE { 2 * ( 1 + 2 ) }
+            :calc NEXT { HERE + 2 }
+            : main
+                add-twice v3 DOUBLE
+                jump NEXT
+            :org 0x300
+                :unpack 0xA data
+            : data
+        ";
+        let rom = compile(source).unwrap();
+        assert_eq!(&rom[..8], [0x12, 0x02, 0x73, 0x06, 0x73, 0x06, 0x12, 0x04]);
+        assert_eq!(rom.len(), 0x104);
+        assert_eq!(&rom[0x100..], [0x60, 0xA3, 0x61, 0x04]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |message: &str, line| AsmError {
+            file: "<source>".to_string(),
+            line,
+            message: message.to_string(),
+        };
+        assert_eq!(compile("clear").err(), Some(error("Missing ': main'", 0)));
+        assert_eq!(
+            compile(": main\n  jump nowhere").err(),
+            Some(error("Undefined name 'nowhere'", 2))
+        );
+        assert_eq!(
+            compile(": main\nv0 := 256").err(),
+            Some(error("256 does not fit in a byte", 2))
+        );
+        assert_eq!(
+            compile(": main\nloop\nv0 += 1").err(),
+            Some(error("Missing 'again'", 3))
+        );
+        assert_eq!(
+            compile(": main\nv0 := 1\n:org 0x200\nv0 := 1").err(),
+            Some(error("Code overlaps at 0x200", 4))
+        );
+        assert_eq!(
+            compile(": main\n:calc x { 1 << 64 }").err(),
+            Some(error("Invalid shift by 64 in :calc", 2))
+        );
+        assert_eq!(
+            compile(": main\n:calc x { 1 >> -1 }").err(),
+            Some(error("Invalid shift by -1 in :calc", 2))
+        );
+        assert_eq!(
+            compile(": main\n:org 0xFFFE\ni := long later\n: later").err(),
+            Some(error("Program does not fit in memory", 3))
+        );
+    }
+}
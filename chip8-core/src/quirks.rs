@@ -0,0 +1,186 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Behaviours that differ between CHIP-8 interpreter implementations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quirks {
+    /// 8XY6/8XYE copy VY into VX before shifting, instead of shifting VX in place
+    pub copy_shift: bool,
+    /// BXNN jumps to XNN + VX, instead of NNN + V0
+    pub offset_jump: bool,
+    /// How FX55/FX65 change the index register after storing or loading
+    pub memory_increment: MemoryIncrement,
+    /// 8XY1/8XY2/8XY3 set VF to 0 after the logical operation
+    pub vf_reset: bool,
+    /// DXYN wraps sprite pixels past the right and bottom edge around the screen, instead of clipping them
+    pub sprite_wrap: bool,
+    /// DXYN in hires mode sets VF to the number of sprite rows that collided or were clipped at
+    /// the bottom edge, as on SUPER-CHIP 1.1, instead of 1 for any collision
+    pub count_collided_rows: bool,
+    /// DXYN blocks the CPU until the next 60 Hz tick, limiting drawing to one sprite per frame
+    pub display_wait: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemoryIncrement {
+    /// I is set to I + X + 1, as on the COSMAC VIP
+    XPlusOne,
+    /// I is set to I + X, as on CHIP-48
+    X,
+    /// I is left unchanged, as on SUPER-CHIP
+    #[default]
+    None,
+}
+
+impl MemoryIncrement {
+    pub const ALL: [MemoryIncrement; 3] = [
+        MemoryIncrement::XPlusOne,
+        MemoryIncrement::X,
+        MemoryIncrement::None,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MemoryIncrement::XPlusOne => "x-plus-one",
+            MemoryIncrement::X => "x",
+            MemoryIncrement::None => "none",
+        }
+    }
+
+    /// Amount to add to I after storing or loading registers V0 to VX
+    pub fn amount(&self, register_x: u8) -> u16 {
+        match self {
+            MemoryIncrement::XPlusOne => register_x as u16 + 1,
+            MemoryIncrement::X => register_x as u16,
+            MemoryIncrement::None => 0,
+        }
+    }
+}
+
+impl fmt::Display for MemoryIncrement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for MemoryIncrement {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<MemoryIncrement, String> {
+        match MemoryIncrement::ALL.iter().find(|increment| increment.name() == name) {
+            Some(increment) => Ok(*increment),
+            None => {
+                let names: Vec<&str> = MemoryIncrement::ALL.iter().map(|increment| increment.name()).collect();
+                Err(format!("Unknown memory increment '{}', expected one of: {}", name, names.join(", ")))
+            }
+        }
+    }
+}
+
+/// Interpreters with well known sets of quirks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    CosmacVip,
+    Chip48,
+    Schip11,
+    XoChip,
+    Modern,
+}
+
+impl Platform {
+    pub const ALL: [Platform; 5] = [
+        Platform::CosmacVip,
+        Platform::Chip48,
+        Platform::Schip11,
+        Platform::XoChip,
+        Platform::Modern,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::CosmacVip => "cosmac-vip",
+            Platform::Chip48 => "chip-48",
+            Platform::Schip11 => "schip-1.1",
+            Platform::XoChip => "xo-chip",
+            Platform::Modern => "modern",
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Platform::CosmacVip => Quirks {
+                copy_shift: true,
+                offset_jump: false,
+                memory_increment: MemoryIncrement::XPlusOne,
+                vf_reset: true,
+                sprite_wrap: false,
+                count_collided_rows: false,
+                display_wait: true,
+            },
+            Platform::Chip48 => Quirks {
+                copy_shift: false,
+                offset_jump: true,
+                memory_increment: MemoryIncrement::X,
+                vf_reset: false,
+                sprite_wrap: false,
+                count_collided_rows: false,
+                display_wait: false,
+            },
+            Platform::Schip11 => Quirks {
+                copy_shift: false,
+                offset_jump: true,
+                memory_increment: MemoryIncrement::None,
+                vf_reset: false,
+                sprite_wrap: false,
+                count_collided_rows: true,
+                display_wait: false,
+            },
+            Platform::XoChip | Platform::Modern => Quirks {
+                copy_shift: true,
+                offset_jump: false,
+                memory_increment: MemoryIncrement::XPlusOne,
+                vf_reset: false,
+                sprite_wrap: true,
+                count_collided_rows: false,
+                display_wait: false,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Platform, String> {
+        match Platform::ALL.iter().find(|platform| platform.name() == name) {
+            Some(platform) => Ok(*platform),
+            None => {
+                let names: Vec<&str> = Platform::ALL.iter().map(|platform| platform.name()).collect();
+                Err(format!("Unknown platform '{}', expected one of: {}", name, names.join(", ")))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::quirks::MemoryIncrement;
+    use crate::quirks::Platform;
+
+    #[test]
+    fn test_platform_names() {
+        for platform in Platform::ALL {
+            assert_eq!(platform.name().parse::<Platform>(), Ok(platform));
+        }
+        assert!("chip-9".parse::<Platform>().is_err());
+        for increment in MemoryIncrement::ALL {
+            assert_eq!(increment.name().parse::<MemoryIncrement>(), Ok(increment));
+        }
+    }
+}
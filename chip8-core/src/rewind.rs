@@ -0,0 +1,175 @@
+use std::collections::VecDeque;
+
+use crate::cpu::Cpu;
+use crate::savestate;
+
+/// Ring buffer of per-frame machine states for stepping backwards through gameplay.
+///
+/// Only the most recent state is kept in full. Every older frame is stored as a delta that turns
+/// the state of the frame after it back into its own state: the two savestates are XORed and runs
+/// of unchanged (zero) bytes are collapsed, so a frame typically costs a few dozen bytes.
+pub struct RewindBuffer {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl RewindBuffer {
+    /// Creates a buffer holding at most `capacity` frames before the latest one.
+    pub fn new(capacity: usize) -> RewindBuffer {
+        RewindBuffer {
+            capacity,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /// Number of frames that can be rewound.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /// Records the state of the current frame.
+    pub fn push(&mut self, cpu: &Cpu) {
+        let state = savestate::save(cpu);
+        if let Some(previous) = self.latest.take() {
+            self.deltas.push_back(encode_delta(&state, &previous));
+            if self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    /// Steps back one frame, returning the machine as it was in that frame.
+    pub fn rewind(&mut self) -> Option<Cpu> {
+        let delta = self.deltas.pop_back()?;
+        let latest = self.latest.as_mut()?;
+        *latest = apply_delta(latest, &delta);
+        // States in the buffer were all produced by `savestate::save`
+        Some(savestate::load(latest).expect("Rewind buffer holds a valid savestate"))
+    }
+}
+
+/// Encodes how to get from `from` to `to` as the length of `to`, followed by pairs of
+/// (unchanged byte count, changed byte count) each followed by the changed bytes XORed.
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let length = from.len().max(to.len());
+    let byte_at = |data: &[u8], i: usize| data.get(i).copied().unwrap_or(0);
+
+    let mut delta = vec![];
+    write_varint(&mut delta, to.len());
+    let mut i = 0;
+    while i < length {
+        let unchanged_start = i;
+        while i < length && byte_at(from, i) == byte_at(to, i) {
+            i += 1;
+        }
+        let changed_start = i;
+        while i < length && byte_at(from, i) != byte_at(to, i) {
+            i += 1;
+        }
+        write_varint(&mut delta, changed_start - unchanged_start);
+        write_varint(&mut delta, i - changed_start);
+        for j in changed_start..i {
+            delta.push(byte_at(from, j) ^ byte_at(to, j));
+        }
+    }
+    delta
+}
+
+fn apply_delta(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut position = 0;
+    let length = read_varint(delta, &mut position);
+    let mut result = from.to_vec();
+    result.resize(from.len().max(length), 0);
+
+    let mut i = 0;
+    while position < delta.len() {
+        i += read_varint(delta, &mut position);
+        let changed = read_varint(delta, &mut position);
+        for byte in &delta[position..position + changed] {
+            result[i] ^= byte;
+            i += 1;
+        }
+        position += changed;
+    }
+    result.truncate(length);
+    result
+}
+
+fn write_varint(output: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        output.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+fn read_varint(input: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = input[*position];
+        *position += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::Cpu;
+    use crate::cpu::Memory;
+    use crate::quirks::Quirks;
+    use crate::rewind::apply_delta;
+    use crate::rewind::encode_delta;
+    use crate::rewind::RewindBuffer;
+
+    #[test]
+    fn test_delta_round_trip() {
+        let cases: [(&[u8], &[u8]); 4] = [
+            (&[1, 2, 3, 4], &[1, 2, 3, 4]),
+            (&[1, 2, 3, 4], &[1, 9, 3, 8]),
+            (&[1, 2, 3, 4], &[1, 2]),
+            (&[1, 2], &[7, 2, 3, 4, 5]),
+        ];
+        for (from, to) in cases {
+            assert_eq!(apply_delta(from, &encode_delta(from, to)), to);
+        }
+    }
+
+    #[test]
+    fn test_rewind() {
+        let rom = vec![
+            0x70, 0x01, // v0 += 1
+            0x22, 0x00, // call 0x200
+        ];
+        let mut cpu = Cpu::new(Memory::new(rom).unwrap(), Quirks::default());
+        let mut buffer = RewindBuffer::new(3);
+        let mut values = vec![];
+        for _ in 0..6 {
+            buffer.push(&cpu);
+            values.push((cpu.registers()[0], cpu.call_stack().len()));
+            cpu.step().unwrap();
+        }
+        assert_eq!(buffer.len(), 3);
+        for expected in values.iter().rev().skip(1).take(3) {
+            let rewound = buffer.rewind().unwrap();
+            assert_eq!((rewound.registers()[0], rewound.call_stack().len()), *expected);
+        }
+        assert!(buffer.rewind().is_none());
+    }
+}
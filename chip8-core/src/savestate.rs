@@ -0,0 +1,239 @@
+use crate::cpu::Cpu;
+use crate::error::SavestateError;
+use crate::quirks::MemoryIncrement;
+use crate::quirks::Quirks;
+
+const MAGIC: &[u8; 4] = b"C8SS";
+// Bump whenever the layout written by any `save_state` changes
+const VERSION: u8 = 1;
+
+/// Appends little-endian values to a savestate buffer.
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub(crate) fn new(magic: &[u8; 4], version: u8) -> StateWriter {
+        let mut writer = StateWriter { data: magic.to_vec() };
+        writer.write_u8(version);
+        writer
+    }
+
+    pub(crate) fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u128(&mut self, value: u128) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes the length of the bytes followed by the bytes themselves
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+/// Reads values written by a `StateWriter` back in the same order.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    /// Starts reading data written by a `StateWriter` with the same magic and version.
+    pub(crate) fn new(
+        data: &'a [u8],
+        magic: &[u8; 4],
+        version: u8,
+    ) -> Result<StateReader<'a>, SavestateError> {
+        let mut reader = StateReader { data, position: 0 };
+        if reader.take(magic.len()).ok() != Some(magic.as_slice()) {
+            return Err(SavestateError::BadMagic);
+        }
+        let data_version = reader.read_u8()?;
+        if data_version != version {
+            return Err(SavestateError::UnsupportedVersion(data_version));
+        }
+        Ok(reader)
+    }
+
+    pub(crate) fn finish(self) -> Result<(), SavestateError> {
+        if self.position != self.data.len() {
+            return Err(SavestateError::Invalid("Trailing data".to_string()));
+        }
+        Ok(())
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], SavestateError> {
+        if self.data.len() - self.position < length {
+            return Err(SavestateError::UnexpectedEnd);
+        }
+        let bytes = &self.data[self.position..self.position + length];
+        self.position += length;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SavestateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SavestateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(SavestateError::Invalid(format!("{} is not a boolean", value))),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, SavestateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, SavestateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SavestateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn read_u128(&mut self) -> Result<u128, SavestateError> {
+        Ok(u128::from_le_bytes(self.take(16)?.try_into().unwrap()))
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], SavestateError> {
+        let length = self.read_u32()?;
+        self.take(length as usize)
+    }
+}
+
+pub(crate) fn write_quirks(writer: &mut StateWriter, quirks: &Quirks) {
+    writer.write_bool(quirks.copy_shift);
+    writer.write_bool(quirks.offset_jump);
+    let memory_increment = MemoryIncrement::ALL
+        .iter()
+        .position(|increment| *increment == quirks.memory_increment)
+        .unwrap();
+    writer.write_u8(memory_increment as u8);
+    writer.write_bool(quirks.vf_reset);
+    writer.write_bool(quirks.sprite_wrap);
+    writer.write_bool(quirks.count_collided_rows);
+    writer.write_bool(quirks.display_wait);
+}
+
+pub(crate) fn read_quirks(reader: &mut StateReader) -> Result<Quirks, SavestateError> {
+    let copy_shift = reader.read_bool()?;
+    let offset_jump = reader.read_bool()?;
+    let memory_increment = reader.read_u8()?;
+    let memory_increment = *MemoryIncrement::ALL
+        .get(memory_increment as usize)
+        .ok_or_else(|| SavestateError::Invalid(format!("Unknown memory increment {}", memory_increment)))?;
+    Ok(Quirks {
+        copy_shift,
+        offset_jump,
+        memory_increment,
+        vf_reset: reader.read_bool()?,
+        sprite_wrap: reader.read_bool()?,
+        count_collided_rows: reader.read_bool()?,
+        display_wait: reader.read_bool()?,
+    })
+}
+
+/// Serializes the complete machine state, including memory, VRAM, keypad and RNG position.
+pub fn save(cpu: &Cpu) -> Vec<u8> {
+    let mut writer = StateWriter::new(MAGIC, VERSION);
+    cpu.save_state(&mut writer);
+    writer.into_data()
+}
+
+/// Restores a machine from data produced by `save`.
+pub fn load(data: &[u8]) -> Result<Cpu, SavestateError> {
+    let mut reader = StateReader::new(data, MAGIC, VERSION)?;
+    let cpu = Cpu::load_state(&mut reader)?;
+    reader.finish()?;
+    Ok(cpu)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::Cpu;
+    use crate::cpu::Memory;
+    use crate::cpu::CALL_STACK_SIZE;
+    use crate::error::SavestateError;
+    use crate::keypad::Keycode;
+    use crate::quirks::Platform;
+    use crate::savestate;
+
+    #[test]
+    fn test_round_trip() {
+        let rom = vec![
+            0x00, 0xFF, // hires
+            0x60, 0x07, // v0 := 7
+            0xF0, 0x29, // i := hex v0
+            0xD0, 0x05, // sprite v0 v0 5
+            0xC1, 0xFF, // v1 := random 0xFF
+            0x22, 0x0E, // call 0x20E
+            0x12, 0x0C, // jump 0x20C
+            0xF0, 0x15, // delay := v0
+            0xC2, 0xFF, // v2 := random 0xFF
+            0x12, 0x10, // jump 0x210
+        ];
+        let mut cpu = Cpu::with_seed(Memory::new(rom).unwrap(), Platform::XoChip.quirks(), 1234);
+        cpu.keypad().on_down(Keycode::KeyA);
+        for _ in 0..7 {
+            cpu.step().unwrap();
+        }
+
+        let state = savestate::save(&cpu);
+        let mut restored = savestate::load(&state).unwrap();
+        assert_eq!(savestate::save(&restored), state);
+        assert_eq!(restored.pc(), cpu.pc());
+        assert_eq!(restored.call_stack(), cpu.call_stack());
+        assert_eq!(restored.quirks(), cpu.quirks());
+        assert!(restored.keypad().is_down(Keycode::KeyA));
+        assert!(restored.vram().is_hires());
+        assert!(restored.vram().get_cell(7, 7));
+
+        // The random number generator continues from the same position
+        cpu.step().unwrap();
+        restored.step().unwrap();
+        assert_eq!(restored.registers(), cpu.registers());
+    }
+
+    #[test]
+    fn test_invalid_state() {
+        assert_eq!(savestate::load(b"NOPE").err(), Some(SavestateError::BadMagic));
+        assert_eq!(savestate::load(b"C8SS\x09").err(), Some(SavestateError::UnsupportedVersion(9)));
+        assert_eq!(savestate::load(b"C8SS\x01\x00").err(), Some(SavestateError::UnexpectedEnd));
+
+        // More nested calls than the call stack holds
+        let cpu = Cpu::new(Memory::new(vec![]).unwrap(), Platform::Modern.quirks());
+        let mut state = savestate::save(&cpu);
+        // After the header, PC, instruction address, opcode, registers and I
+        let call_stack_length = 5 + 3 * 2 + 4 + 16 + 2;
+        assert_eq!(state[call_stack_length..call_stack_length + 2], [0, 0]);
+        state[call_stack_length] = CALL_STACK_SIZE as u8 + 1;
+        assert!(matches!(savestate::load(&state), Err(SavestateError::Invalid(_))));
+    }
+}
@@ -0,0 +1,144 @@
+use std::io;
+use std::io::Write;
+
+use crate::cpu::Cpu;
+use crate::error::DecodeError;
+use crate::instruction::Instruction;
+
+/// Writes a line of text for every executed instruction, meant to be diffed against traces of
+/// other emulators:
+///
+/// ```text
+/// 0000000002 0204 F033 BcdConversion(0) | [0300]=01 [0301]=02 [0302]=03
+/// 0000000004 0208 8014 ArithmeticAdd { register_x: 0, register_y: 1 } | V0=7A VF=01
+/// ```
+///
+/// Each line holds the number of instructions executed before it, the address and first word of
+/// the instruction and the decoded instruction, followed by every register and I that changed
+/// and every byte of memory the instruction wrote, or the error that stopped it.
+pub struct Tracer<W: Write> {
+    output: W,
+    cycle: u64,
+    // First failure to write, reported when finishing so stepping never fails because of tracing
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(output: W) -> Tracer<W> {
+        Tracer {
+            output,
+            cycle: 0,
+            error: None,
+        }
+    }
+
+    /// Executes one instruction like `Cpu::step` and traces it.
+    pub fn step(&mut self, cpu: &mut Cpu) -> Result<(), DecodeError> {
+        let pc = cpu.pc();
+        let opcode = cpu.memory().get_word(pc);
+        let registers = cpu.registers().to_vec();
+        let index = cpu.index_register();
+        let instruction = Cpu::decode_at(opcode, pc);
+        let written = match instruction {
+            Ok(Instruction::Store(register)) => register as u16 + 1,
+            Ok(Instruction::StoreRange {
+                register_x,
+                register_y,
+            }) => register_x.abs_diff(register_y) as u16 + 1,
+            Ok(Instruction::BcdConversion(_)) => 3,
+            _ => 0,
+        };
+
+        let result = cpu.step();
+
+        let mut line = format!("{:010} {:04X} {:04X} ", self.cycle, pc, opcode);
+        match instruction {
+            Ok(Instruction::SetILong) => {
+                line += &format!("SetILong {:#06X}", cpu.memory().get_word(pc.wrapping_add(2)))
+            }
+            Ok(instruction) => line += &format!("{:?}", instruction),
+            Err(_) => line += "?",
+        }
+        let mut changes = vec![];
+        for (register, (old, new)) in registers.iter().zip(cpu.registers()).enumerate() {
+            if old != new {
+                changes.push(format!("V{:X}={:02X}", register, new));
+            }
+        }
+        if cpu.index_register() != index {
+            changes.push(format!("I={:04X}", cpu.index_register()));
+        }
+        if result.is_ok() {
+            for offset in 0..written {
+                let address = index.wrapping_add(offset);
+                changes.push(format!("[{:04X}]={:02X}", address, cpu.memory().get(address)));
+            }
+        }
+        if let Err(error) = result {
+            changes.push(format!("error: {}", error));
+        }
+        if !changes.is_empty() {
+            line += " | ";
+            line += &changes.join(" ");
+        }
+
+        self.cycle += 1;
+        if self.error.is_none() {
+            if let Err(error) = writeln!(self.output, "{}", line) {
+                self.error = Some(error);
+            }
+        }
+        result
+    }
+
+    /// Flushes the trace, reporting the first write that failed.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        self.output.flush()?;
+        Ok(self.output)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::Cpu;
+    use crate::cpu::Memory;
+    use crate::quirks::Quirks;
+    use crate::trace::Tracer;
+
+    #[test]
+    fn test_trace() {
+        let rom = vec![
+            0x60, 0x7B, // V0 := 123
+            0xA3, 0x00, // I := 0x300
+            0xF0, 0x33, // BCD V0
+            0x61, 0xFF, // V1 := 0xFF
+            0x80, 0x14, // V0 += V1
+            0xF1, 0x55, // Store V0 - V1
+            0xFF, 0xFF, // Unknown
+        ];
+        let mut cpu = Cpu::new(Memory::new(rom).unwrap(), Quirks::default());
+        let mut tracer = Tracer::new(vec![]);
+        for _ in 0..6 {
+            tracer.step(&mut cpu).unwrap();
+        }
+        assert!(tracer.step(&mut cpu).is_err());
+
+        let trace = String::from_utf8(tracer.finish().unwrap()).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "0000000000 0200 607B SetVX { register: 0, value: 123 } | V0=7B",
+                "0000000001 0202 A300 SetI(768) | I=0300",
+                "0000000002 0204 F033 BcdConversion(0) | [0300]=01 [0301]=02 [0302]=03",
+                "0000000003 0206 61FF SetVX { register: 1, value: 255 } | V1=FF",
+                "0000000004 0208 8014 ArithmeticAdd { register_x: 0, register_y: 1 } | V0=7A VF=01",
+                "0000000005 020A F155 Store(1) | [0300]=7A [0301]=FF",
+                "0000000006 020C FFFF ? | error: Unknown F-prefix instruction: 0xFFFF at 0x20C",
+            ]
+        );
+    }
+}
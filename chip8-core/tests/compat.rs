@@ -0,0 +1,78 @@
+use std::fs;
+use std::path::Path;
+
+use chip8_core::asm;
+use chip8_core::compat;
+use chip8_core::compat::Outcome;
+use chip8_core::quirks::MemoryIncrement;
+use chip8_core::quirks::Platform;
+
+#[test]
+fn test_compat_suite() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/compat");
+    let text = fs::read_to_string(directory.join("suite.txt")).unwrap();
+    let cases = compat::parse_suite(&text).unwrap();
+
+    let mut failures = vec![];
+    for case in &cases {
+        let outcome = compat::check(case, &directory);
+        let failed = outcome.is_failure(case);
+        match outcome {
+            Outcome::Pass => {}
+            // Optional cases whose ROM is missing or whose screen is not recorded are skipped
+            _ if !failed => {}
+            Outcome::Missing => failures.push(format!("{}: {} not found", case.name, case.rom)),
+            Outcome::Unrecorded { actual, screen } => failures.push(format!(
+                "{} has no screen recorded (hash {:016x}):\n{}",
+                case.name, actual, screen
+            )),
+            Outcome::Fail { actual, screen } => failures.push(format!(
+                "{} drew a different screen (hash {:016x}):\n{}",
+                case.name, actual, screen
+            )),
+            Outcome::Error(error) => failures.push(format!("{}: {}", case.name, error)),
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+/// Digits quirks.asm should draw with the quirks of a platform, following the table at its top.
+fn expected_quirk_digits(platform: Platform) -> [u8; 5] {
+    let quirks = platform.quirks();
+    [
+        if quirks.vf_reset { 0 } else { 1 },
+        if quirks.copy_shift { 4 } else { 1 },
+        if quirks.copy_shift { 8 } else { 2 },
+        match quirks.memory_increment {
+            MemoryIncrement::XPlusOne => 3,
+            MemoryIncrement::X => 2,
+            MemoryIncrement::None => 1,
+        },
+        if quirks.offset_jump { 1 } else { 0 },
+    ]
+}
+
+// The recorded hashes of quirks.asm only show what the emulator drew when they were recorded, so
+// also compare its screen with one drawing the digits the quirks should produce
+#[test]
+fn test_quirk_digits() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/compat");
+    let rom = compat::load_rom(&directory.join("quirks.asm")).unwrap();
+    for platform in Platform::ALL {
+        let mut source = "LD V5, 4\nLD V6, 4\n".to_string();
+        for digit in expected_quirk_digits(platform) {
+            source += &format!("LD V0, {}\nLD F, V0\nDRW V5, V6, 5\nADD V5, 5\n", digit);
+        }
+        // The digit across the right edge only depends on the sprite wrap quirk
+        source += "LD V0, 8\nLD F, V0\nLD V5, 62\nLD V6, 20\nDRW V5, V6, 5\nend: JP end\n";
+        let expected = compat::run(asm::assemble(&source).unwrap(), platform, 30).unwrap();
+        let actual = compat::run(rom.clone(), platform, 30).unwrap();
+        assert_eq!(
+            compat::vram_hash(actual.vram()),
+            compat::vram_hash(expected.vram()),
+            "quirks.asm on {} should show {:?}",
+            platform,
+            expected_quirk_digits(platform)
+        );
+    }
+}
@@ -0,0 +1,63 @@
+; Shows the results of quirk-sensitive instructions as a row of hex digits, so every set of
+; quirks draws a different screen. Digits from left to right:
+;   1. VF after 8XY1 set it to 1 first            (0 with the VF reset quirk)
+;   2. VX after 8XY6 with VX = 2 and VY = 8       (4 with the copy shift quirk, else 1)
+;   3. VX after 8XYE with VX = 1 and VY = 4       (8 with the copy shift quirk, else 2)
+;   4. V0 after reading V0-V1 with FX65 twice     (3 when I increments by X + 1, 2 when by X,
+;                                                 1 when I is left unchanged)
+;   5. Which entry BNNN jumps to                  (1 with the offset jump quirk, else 0)
+; Finally a digit is drawn across the right edge, which wraps with the sprite wrap quirk.
+
+X       equ 4
+Y       equ 4
+
+        LD V5, X
+        LD V6, Y
+
+        LD VF, 1
+        LD V0, 0
+        OR V0, V0
+        LD V0, VF
+        CALL show
+
+        LD V0, 2
+        LD V1, 8
+        SHR V0, V1
+        CALL show
+
+        LD V0, 1
+        LD V1, 4
+        SHL V0, V1
+        CALL show
+
+        LD I, table
+        LD V1, [I]
+        LD V1, [I]
+        CALL show
+
+        LD V0, 0
+        LD V2, 4
+        JP V0, jumps
+
+jumps:  JP plain
+        JP plain
+        JP offset
+plain:  LD V0, 0
+        JP jumped
+offset: LD V0, 1
+jumped: CALL show
+
+        LD V0, 8
+        LD V5, 62
+        LD V6, 20
+        CALL show
+
+end:    JP end
+
+; Draws the low digit of V0 at (V5, V6) and moves right
+show:   LD F, V0
+        DRW V5, V6, 5
+        ADD V5, 5
+        RET
+
+table:  db 1, 2, 3, 4
@@ -0,0 +1,27 @@
+use chip8_core::asm;
+
+use clap::Parser;
+
+use std::fs;
+use std::path::Path;
+
+/// Assembles CHIP-8 source in Cowgod's mnemonics into a ROM
+#[derive(Parser)]
+struct Args {
+    #[arg(long)]
+    source: String,
+    /// Defaults to the source path with a .ch8 extension
+    #[arg(long)]
+    output: Option<String>,
+}
+
+fn main() -> Result<(), String> {
+    let args = Args::parse();
+    let source = Path::new(&args.source);
+    let rom_data = asm::assemble_file(source).map_err(|e| e.to_string())?;
+    let output = match args.output {
+        Some(output) => output.into(),
+        None => source.with_extension("ch8"),
+    };
+    fs::write(&output, rom_data).map_err(|e| format!("Failed to write {}: {}", output.display(), e))
+}
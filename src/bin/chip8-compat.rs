@@ -0,0 +1,65 @@
+use chip8_core::compat;
+use chip8_core::compat::Outcome;
+
+use clap::Parser;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Runs test ROMs headlessly and compares their final screens with recorded ones
+#[derive(Parser)]
+struct Args {
+    #[arg(long, default_value = "chip8-core/tests/compat/suite.txt")]
+    suite: String,
+    /// Record the screens of failing and unrecorded cases as the expected ones
+    #[arg(long)]
+    update: bool,
+}
+
+fn main() -> Result<(), String> {
+    let args = Args::parse();
+    let text = fs::read_to_string(&args.suite)
+        .map_err(|e| format!("Failed to read {}: {}", args.suite, e))?;
+    let cases = compat::parse_suite(&text).map_err(|e| format!("{}: {}", args.suite, e))?;
+    let directory = Path::new(&args.suite).parent().unwrap_or(Path::new("."));
+
+    let mut failures = 0;
+    let mut hashes = HashMap::new();
+    for case in &cases {
+        let outcome = compat::check(case, directory);
+        let failed = outcome.is_failure(case);
+        match outcome {
+            Outcome::Pass => println!("PASS  {}", case.name),
+            Outcome::Fail { actual, screen } => {
+                println!("FAIL  {} (screen hash {:016x})", case.name, actual);
+                print!("{}", screen);
+                hashes.insert(case.name.clone(), actual);
+            }
+            Outcome::Unrecorded { actual, screen } => {
+                println!("NEW   {} (screen hash {:016x})", case.name, actual);
+                print!("{}", screen);
+                hashes.insert(case.name.clone(), actual);
+            }
+            Outcome::Missing if case.optional => {
+                println!("SKIP  {} ({} not found)", case.name, case.rom)
+            }
+            Outcome::Missing => println!("MISSING {} ({} not found)", case.name, case.rom),
+            Outcome::Error(error) => println!("ERROR {}: {}", case.name, error),
+        }
+        // Recording the screen fixes failures due to a changed or unrecorded screen
+        if failed && !(args.update && hashes.contains_key(&case.name)) {
+            failures += 1;
+        }
+    }
+
+    if args.update && !hashes.is_empty() {
+        fs::write(&args.suite, compat::update_suite(&text, &hashes))
+            .map_err(|e| format!("Failed to write {}: {}", args.suite, e))?;
+        println!("Recorded {} screens in {}", hashes.len(), args.suite);
+    }
+    if failures > 0 {
+        return Err(format!("{} of {} cases failed", failures, cases.len()));
+    }
+    Ok(())
+}
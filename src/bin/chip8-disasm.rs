@@ -0,0 +1,33 @@
+use chip8_core::disasm;
+use chip8_core::disasm::Syntax;
+
+use clap::Parser;
+
+use std::fs;
+
+/// Prints a CHIP-8 ROM as assembly
+#[derive(Parser)]
+struct Args {
+    #[arg(long)]
+    rom: String,
+    /// Mnemonics to print: octo or cowgod
+    #[arg(long, default_value_t = Syntax::Octo)]
+    syntax: Syntax,
+    /// Follow jumps, calls and skips from the entry point and list everything else as data
+    #[arg(long)]
+    recursive: bool,
+}
+
+fn main() -> Result<(), String> {
+    let args = Args::parse();
+    let rom_data = fs::read(&args.rom).map_err(|e| format!("Failed to read {}: {}", args.rom, e))?;
+    let lines = if args.recursive {
+        disasm::disassemble_reachable(&rom_data, args.syntax)
+    } else {
+        disasm::disassemble(&rom_data, args.syntax)
+    };
+    for line in lines {
+        println!("{}", line);
+    }
+    Ok(())
+}
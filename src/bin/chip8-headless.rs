@@ -0,0 +1,37 @@
+use chip8_rs::headless;
+use chip8_rs::options::MachineArgs;
+
+use clap::Parser;
+
+/// Runs a ROM without display or audio, so without needing SDL, then dumps the final registers,
+/// VRAM and memory
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    machine: MachineArgs,
+    /// Number of 60 Hz frames to run, or every frame of the movie given with --play
+    #[arg(long)]
+    frames: Option<u64>,
+    /// Number of instructions to run
+    #[arg(long)]
+    instructions: Option<u64>,
+}
+
+fn main() -> Result<(), String> {
+    let args = Args::parse();
+    let mut machine = args.machine.start()?;
+    // Play the whole movie unless told otherwise
+    let frames = match &machine.playback {
+        Some(movie) if args.instructions.is_none() => args.frames.or(Some(movie.len() as u64)),
+        _ => args.frames,
+    };
+    headless::run(
+        &mut machine.cpu,
+        frames,
+        args.instructions,
+        machine.instructions_per_second,
+        args.machine.on_decode_error,
+        machine.playback.as_ref(),
+        machine.tracer,
+    )
+}
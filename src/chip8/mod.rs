@@ -1,4 +0,0 @@
-pub mod cpu;
-pub mod instruction;
-pub mod display;
-pub mod keypad;
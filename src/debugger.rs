@@ -0,0 +1,118 @@
+use std::io;
+use std::io::Write;
+
+use chip8_core::cpu::Cpu;
+use chip8_core::debugger::Command;
+use chip8_core::debugger::Debugger;
+use chip8_core::dump;
+
+const HELP: &str = "\
+c, continue          Resume execution
+s, step [count]      Execute instructions one at a time (default 1)
+b, break <addr>      Pause before executing the instruction at addr
+d, delete <addr>     Remove the breakpoint at addr
+w, watch <target>    Pause when v0-vf, i or the byte at an address changes
+u, unwatch <target>  Remove a watchpoint
+l, list              List breakpoints and watchpoints
+r, registers         Print registers, I, call stack and timers
+m, memory <addr> [n] Print n bytes of memory (default 16)
+q, quit              Exit the emulator
+Addresses are hexadecimal.";
+
+/// What the frontend should do after a debugger command.
+pub enum Action {
+    Continue,
+    Quit,
+}
+
+/// Prints the instruction the CPU is about to execute.
+pub fn print_location(cpu: &Cpu) {
+    let raw = cpu.memory().get_word(cpu.pc());
+    match Cpu::decode_at(raw, cpu.pc()) {
+        Ok(instruction) => println!("{:#05X}: {:04X}  {:?}", cpu.pc(), raw, instruction),
+        Err(error) => println!("{:#05X}: {:04X}  {}", cpu.pc(), raw, error),
+    }
+}
+
+/// Reads a command from stdin and runs it against the paused CPU.
+pub fn prompt(debugger: &mut Debugger, cpu: &mut Cpu) -> Action {
+    print!("(chip8) ");
+    io::stdout().flush().unwrap();
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+        // Nobody is left to resume the CPU once stdin is closed
+        return Action::Quit;
+    }
+    if line.trim().is_empty() {
+        return Action::Continue;
+    }
+    let command = match line.parse() {
+        Ok(command) => command,
+        Err(error) => {
+            println!("{}", error);
+            return Action::Continue;
+        }
+    };
+
+    match command {
+        Command::Continue => debugger.resume(),
+        Command::Step(count) => {
+            for _ in 0..count {
+                match debugger.step(cpu) {
+                    Ok(None) => {}
+                    Ok(Some(reason)) => {
+                        println!("{}", reason);
+                        break;
+                    }
+                    Err(error) => {
+                        println!("{}", error);
+                        break;
+                    }
+                }
+            }
+            print_location(cpu);
+        }
+        Command::Break(address) => {
+            if debugger.add_breakpoint(address) {
+                println!("Breakpoint at {:#05X}", address);
+            }
+        }
+        Command::Delete(address) => {
+            if !debugger.remove_breakpoint(address) {
+                println!("No breakpoint at {:#05X}", address);
+            }
+        }
+        Command::Watch(watchpoint) => {
+            if debugger.add_watchpoint(watchpoint, cpu) {
+                println!("Watching {}", watchpoint);
+            }
+        }
+        Command::Unwatch(watchpoint) => {
+            if !debugger.remove_watchpoint(watchpoint) {
+                println!("Not watching {}", watchpoint);
+            }
+        }
+        Command::List => {
+            for address in debugger.breakpoints() {
+                println!("Breakpoint at {:#05X}", address);
+            }
+            for watchpoint in debugger.watchpoints() {
+                println!("Watching {}", watchpoint);
+            }
+        }
+        Command::Registers => print!("{}", dump::format_registers(cpu)),
+        Command::Memory { address, length } => {
+            for row in (0..length).step_by(16) {
+                let start = address.wrapping_add(row);
+                print!("{:04X}:", start);
+                for offset in 0..(length - row).min(16) {
+                    print!(" {:02X}", cpu.memory().get(start.wrapping_add(offset)));
+                }
+                println!();
+            }
+        }
+        Command::Help => println!("{}", HELP),
+        Command::Quit => return Action::Quit,
+    }
+    Action::Continue
+}
@@ -0,0 +1,82 @@
+use chip8_core::cpu::Cpu;
+use chip8_core::dump;
+use chip8_core::movie::instructions_in_frame;
+use chip8_core::movie::Movie;
+use chip8_core::trace::Tracer;
+
+use std::io::Write;
+
+use crate::options::handle_decode_error;
+use crate::options::DecodeErrorPolicy;
+
+/// Runs the CPU without a display or audio device, then prints the final machine state.
+///
+/// Every frame executes its share of `instructions_per_second` (see `instructions_in_frame`)
+/// followed by a 60 Hz timer tick, so the result only depends on the ROM, the quirks and the
+/// limits. Running stops after `frames` frames or `instructions` instructions, whichever limit is
+/// reached first. When `playback` is given, each frame first applies the keypad state recorded
+/// for it. With a `tracer`, every instruction is traced.
+pub fn run(
+    cpu: &mut Cpu,
+    frames: Option<u64>,
+    instructions: Option<u64>,
+    instructions_per_second: u32,
+    policy: DecodeErrorPolicy,
+    playback: Option<&Movie>,
+    mut tracer: Option<Tracer<Box<dyn Write>>>,
+) -> Result<(), String> {
+    if frames.is_none() && instructions.is_none() {
+        return Err("Headless mode needs --frames or --instructions".to_string());
+    }
+    let mut frame_count: u64 = 0;
+    let mut instruction_count: u64 = 0;
+    let mut halted = false;
+
+    'running: while !halted && frames.map_or(true, |frames| frame_count < frames) {
+        if let Some(down_mask) = playback.and_then(|movie| movie.frame(frame_count as usize)) {
+            cpu.keypad().set_down_mask(down_mask);
+        }
+        for _ in 0..instructions_in_frame(instructions_per_second, frame_count) {
+            if instructions.is_some_and(|instructions| instruction_count >= instructions) {
+                break 'running;
+            }
+            if cpu.is_waiting_for_vblank() || cpu.has_exited() {
+                break;
+            }
+            instruction_count += 1;
+            let result = match &mut tracer {
+                Some(tracer) => tracer.step(cpu),
+                None => cpu.step(),
+            };
+            if let Err(error) = result {
+                halted = handle_decode_error(cpu, error, policy);
+                if halted {
+                    break;
+                }
+            }
+        }
+        cpu.tick_timers();
+        frame_count += 1;
+    }
+
+    println!(
+        "Ran {} frames, {} instructions{}",
+        frame_count,
+        instruction_count,
+        if halted { " (halted)" } else { "" }
+    );
+    println!("Seed: {}", cpu.seed());
+    print!("{}", dump::format_registers(cpu));
+    println!("VRAM ({}x{}):", cpu.vram().width, cpu.vram().height);
+    print!("{}", dump::format_vram(cpu.vram()));
+    println!("Memory:");
+    print!("{}", dump::format_memory(cpu.memory()));
+
+    if let Some(tracer) = tracer {
+        tracer.finish().map_err(|e| format!("Failed to write trace: {}", e))?;
+    }
+    if halted {
+        return Err("Halted on decode error".to_string());
+    }
+    Ok(())
+}
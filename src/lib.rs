@@ -0,0 +1,4 @@
+//! Frontend code that does not need SDL, shared by the emulator and the headless runner.
+
+pub mod headless;
+pub mod options;
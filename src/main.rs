@@ -1,13 +1,29 @@
-pub mod chip8;
-
-use chip8::cpu::Cpu;
-use chip8::cpu::Memory;
+mod debugger;
+
+use chip8_core::cpu::AudioState;
+use chip8_core::cpu::Cpu;
+use chip8_core::debugger::Debugger;
+use chip8_core::debugger::StopReason;
+use chip8_core::error::DecodeError;
+use chip8_core::gdb::GdbStub;
+use chip8_core::gdb::Session;
+use chip8_core::keypad::Keycode as Chip8Keycode;
+use chip8_core::movie::instructions_in_frame;
+use chip8_core::movie::Movie;
+use chip8_core::rewind::RewindBuffer;
+use chip8_core::savestate;
+
+use chip8_rs::options::handle_decode_error;
+use chip8_rs::options::load_state_file;
+use chip8_rs::options::DecodeErrorPolicy;
+use chip8_rs::options::MachineArgs;
 
 extern crate sdl2;
 
 use sdl2::audio::AudioDevice;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
+use sdl2::keyboard::Scancode;
 use sdl2::pixels::Color;
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::AudioSubsystem;
@@ -43,9 +59,45 @@ impl AudioCallback for SquareWave {
     }
 }
 
+// Plays the XO-CHIP 1-bit audio pattern, or the square wave if no pattern has been loaded
+struct PatternWave {
+    square: SquareWave,
+    pattern: Option<[u8; 16]>,
+    // Pattern bits to advance per output sample
+    bit_inc: f32,
+    position: f32,
+    sample_rate: f32,
+}
+
+impl PatternWave {
+    fn set_state(&mut self, state: &AudioState) {
+        self.pattern = state.pattern;
+        self.bit_inc = state.playback_rate() / self.sample_rate;
+    }
+}
+
+impl AudioCallback for PatternWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        let pattern = match self.pattern {
+            Some(pattern) => pattern,
+            None => return self.square.callback(out),
+        };
+        let volume = self.square.volume;
+        for x in out.iter_mut() {
+            let bit = self.position as usize;
+            let is_set = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+            *x = if is_set { volume } else { -volume };
+            self.position = (self.position + self.bit_inc) % 128.0;
+        }
+    }
+}
+
 struct Beeper {
-    device: AudioDevice<SquareWave>,
-    is_beeping: bool
+    device: AudioDevice<PatternWave>,
+    is_beeping: bool,
+    state: Option<AudioState>,
 }
 
 impl Beeper {
@@ -59,10 +111,16 @@ impl Beeper {
         let device = audio
             .open_playback(None, &audio_spec, |spec| {
                 // initialize the audio callback
-                SquareWave {
-                    phase_inc: 220.0 / spec.freq as f32,
-                    phase: 0.0,
-                    volume: 0.25,
+                PatternWave {
+                    square: SquareWave {
+                        phase_inc: 220.0 / spec.freq as f32,
+                        phase: 0.0,
+                        volume: 0.25,
+                    },
+                    pattern: None,
+                    bit_inc: 0.0,
+                    position: 0.0,
+                    sample_rate: spec.freq as f32,
                 }
             })
             .unwrap();
@@ -70,9 +128,18 @@ impl Beeper {
         Beeper {
             device,
             is_beeping: false,
+            state: None,
         }
     }
 
+    fn update(&mut self, state: AudioState) {
+        if self.state != Some(state) {
+            self.device.lock().set_state(&state);
+            self.state = Some(state);
+        }
+        self.set_beeping(state.playing);
+    }
+
     fn set_beeping(& mut self, beep: bool) {
         if self.is_beeping == beep {
             return;
@@ -86,26 +153,132 @@ impl Beeper {
     }
 }
 
+// Colours for each combination of the two XO-CHIP bitplanes
+const PALETTE: [Color; 4] = [
+    Color::RGB(0, 0, 0),
+    Color::RGB(255, 255, 255),
+    Color::RGB(170, 170, 170),
+    Color::RGB(85, 85, 85),
+];
+
 #[derive(Parser)]
 struct Args {
-    #[arg(long)]
-    rom: String,
-    #[arg(long)]
-    use_copy_shift: bool,
-    #[arg(long)]
-    use_offset_jump_quirk: bool,
-    #[arg(long, default_value_t = 700)]
-    instructions_per_second: u32,
+    #[command(flatten)]
+    machine: MachineArgs,
+    /// How far back holding Backspace can rewind gameplay
+    #[arg(long, default_value_t = 300)]
+    rewind_seconds: u32,
+    /// Record the keypad state of every frame into a movie file, written on exit
+    #[arg(long, conflicts_with_all = ["load_state", "play"])]
+    record: Option<String>,
+    /// Start paused in the interactive debugger, which F10 also breaks into
+    #[arg(long, conflicts_with_all = ["record", "play"])]
+    debug: bool,
+    /// Wait for GDB to connect to this port on localhost and let it control the CPU
+    #[arg(long, conflicts_with_all = ["record", "play", "debug"])]
+    gdb: Option<u16>,
+}
+
+/// Maps the left side of a QWERTY keyboard onto the CHIP-8 keypad
+fn decode_scancode(scancode: Scancode) -> Option<Chip8Keycode> {
+    match scancode {
+        Scancode::Num1 => Some(Chip8Keycode::Key1),
+        Scancode::Num2 => Some(Chip8Keycode::Key2),
+        Scancode::Num3 => Some(Chip8Keycode::Key3),
+        Scancode::Num4 => Some(Chip8Keycode::KeyC),
+
+        Scancode::Q => Some(Chip8Keycode::Key4),
+        Scancode::W => Some(Chip8Keycode::Key5),
+        Scancode::E => Some(Chip8Keycode::Key6),
+        Scancode::R => Some(Chip8Keycode::KeyD),
+
+        Scancode::A => Some(Chip8Keycode::Key7),
+        Scancode::S => Some(Chip8Keycode::Key8),
+        Scancode::D => Some(Chip8Keycode::Key9),
+        Scancode::F => Some(Chip8Keycode::KeyE),
+
+        Scancode::Z => Some(Chip8Keycode::KeyA),
+        Scancode::X => Some(Chip8Keycode::Key0),
+        Scancode::C => Some(Chip8Keycode::KeyB),
+        Scancode::V => Some(Chip8Keycode::KeyF),
+
+        _ => None,
+    }
+}
+
+// Number of savestate slots, selected with F1 to F4
+const SAVESTATE_SLOTS: u32 = 4;
+
+fn savestate_path(rom: &str, slot: u32) -> String {
+    format!("{}.state{}", rom, slot)
+}
+
+fn save_state_file(cpu: &Cpu, path: &str) -> Result<(), String> {
+    fs::write(path, savestate::save(cpu)).map_err(|e| format!("Failed to write {}: {}", path, e))
+}
+
+/// What happened while running a frame
+struct Frame {
+    executed: u32,
+    // Error that halted the CPU
+    error: Option<DecodeError>,
+    // Breakpoint or watchpoint that paused the debugger
+    stop: Option<StopReason>,
+}
+
+/// Runs the instructions of one 60 Hz frame the way `Movie` expects, stopping early while the CPU
+/// waits for vblank or has exited, or when the debugger pauses it.
+fn run_frame(
+    cpu: &mut Cpu,
+    debugger: &mut Debugger,
+    instructions: u32,
+    policy: DecodeErrorPolicy,
+) -> Frame {
+    let mut frame = Frame {
+        executed: 0,
+        error: None,
+        stop: None,
+    };
+    while frame.executed < instructions
+        && !cpu.is_waiting_for_vblank()
+        && !cpu.has_exited()
+        && !debugger.is_paused()
+    {
+        frame.executed += 1;
+        match debugger.step(cpu) {
+            Ok(None) => {}
+            Ok(Some(reason)) => {
+                debugger.pause();
+                frame.stop = Some(reason);
+            }
+            Err(error) => {
+                if !handle_decode_error(cpu, error, policy) {
+                    continue;
+                }
+                if policy == DecodeErrorPolicy::Debug {
+                    debugger.pause();
+                } else {
+                    frame.error = Some(error);
+                    break;
+                }
+            }
+        }
+    }
+    frame
 }
 
 pub fn main() -> Result<(), String> {
     let args = Args::parse();
-    let rom_data = fs::read(args.rom).unwrap();
-    let mut cpu = Cpu::new(
-        Memory::new(rom_data),
-        args.use_copy_shift,
-        args.use_offset_jump_quirk,
-    );
+    let machine = args.machine.start()?;
+    let mut cpu = machine.cpu;
+    let playback = machine.playback;
+    let instructions_per_second = machine.instructions_per_second;
+    let mut recording = args
+        .record
+        .as_ref()
+        .map(|_| Movie::new(&machine.rom, cpu.seed(), cpu.quirks(), instructions_per_second));
+    // Rewinding and loading states would make the session impossible to replay
+    let is_movie = recording.is_some() || playback.is_some();
 
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
@@ -160,17 +333,39 @@ pub fn main() -> Result<(), String> {
     let mut frame_times: Vec<Duration> = vec![];
     let mut last_frame_end = Instant::now();
 
-    let instructions_per_second = args.instructions_per_second;
-    let cpu_tick_duration = Duration::from_secs(1) / instructions_per_second;
-    let mut cpu_timer = Instant::now();
+    let mut instruction_count: u32 = 0;
+    let mut frame_count: usize = 0;
 
-    let mut cpu_tick_times: Vec<Duration> = vec![];
-    let mut last_cpu_tick = Instant::now();
+    let mut halted = false;
+    let mut savestate_slot = 1;
+
+    let mut debugger = Debugger::new();
+    if let Some(tracer) = machine.tracer {
+        debugger.set_tracer(tracer);
+    }
+    if args.debug {
+        debugger.pause();
+        debugger::print_location(&cpu);
+    }
+    let mut gdb = match args.gdb {
+        Some(port) => {
+            println!("Waiting for GDB to connect to 127.0.0.1:{}", port);
+            let stub = GdbStub::listen(port).map_err(|e| e.to_string())?;
+            // GDB expects the program to be stopped when it attaches
+            debugger.pause();
+            Some(stub)
+        }
+        None => None,
+    };
+
+    // One snapshot is recorded per 60 Hz frame, and restored per frame while Backspace is held
+    let mut rewind_buffer = RewindBuffer::new(args.rewind_seconds as usize * 60);
+    let mut rewinding = false;
 
     'running: loop {
         if print_timer.elapsed() > print_duration {
             let average_frame_time =
-                (&frame_times).into_iter().sum::<Duration>() / (frame_times.len() as u32);
+                frame_times.iter().sum::<Duration>() / (frame_times.len() as u32);
             let frames_per_second =
                 Duration::from_secs(1).as_micros() / average_frame_time.as_micros();
             println!(
@@ -178,18 +373,13 @@ pub fn main() -> Result<(), String> {
                 frames_per_second, average_frame_time
             );
 
-            let average_cpu_tick_time =
-                (&cpu_tick_times).into_iter().sum::<Duration>() / (cpu_tick_times.len() as u32);
             let cpu_ticks_per_second =
-                Duration::from_secs(1).as_micros() / average_cpu_tick_time.as_micros();
-            println!(
-                "Average CPU ticks per second {} (tick time: {:?})",
-                cpu_ticks_per_second, average_cpu_tick_time
-            );
+                instruction_count as f64 / print_timer.elapsed().as_secs_f64();
+            println!("Average CPU ticks per second {:.0}", cpu_ticks_per_second);
 
             print_timer = Instant::now();
             frame_times.clear();
-            cpu_tick_times.clear();
+            instruction_count = 0;
         }
 
         for event in event_pump.poll_iter() {
@@ -200,53 +390,194 @@ pub fn main() -> Result<(), String> {
                     ..
                 } => break 'running,
                 Event::KeyDown {
-                    scancode: Some(scancode),
+                    keycode: Some(keycode @ (Keycode::F1 | Keycode::F2 | Keycode::F3 | Keycode::F4)),
+                    ..
+                } => {
+                    savestate_slot = keycode as u32 - Keycode::F1 as u32 + 1;
+                    println!("Selected savestate slot {}/{}", savestate_slot, SAVESTATE_SLOTS);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    repeat: false,
                     ..
                 } => {
-                    cpu.keypad().on_down(scancode);
+                    let path = savestate_path(&args.machine.rom, savestate_slot);
+                    match save_state_file(&cpu, &path) {
+                        Ok(()) => println!("Saved state to {}", path),
+                        Err(error) => eprintln!("{}", error),
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    repeat: false,
+                    ..
+                } if !is_movie && !debugger.is_paused() => {
+                    println!("Paused");
+                    debugger.pause();
+                    match &mut gdb {
+                        Some(stub) => stub.report_stop(None).map_err(|e| e.to_string())?,
+                        None => debugger::print_location(&cpu),
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } if !is_movie => rewinding = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = false,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    repeat: false,
+                    ..
+                } if !is_movie => {
+                    let path = savestate_path(&args.machine.rom, savestate_slot);
+                    match load_state_file(&path) {
+                        Ok(loaded) => {
+                            cpu = loaded;
+                            halted = false;
+                            rewind_buffer.clear();
+                            println!("Loaded state from {}", path);
+                        }
+                        Err(error) => eprintln!("{}", error),
+                    }
+                }
+                // The keypad follows the movie during playback
+                Event::KeyDown {
+                    scancode: Some(scancode),
+                    ..
+                } if playback.is_none() => {
+                    if let Some(keycode) = decode_scancode(scancode) {
+                        cpu.keypad().on_down(keycode);
+                    }
                 }
                 Event::KeyUp {
                     scancode: Some(scancode),
                     ..
-                } => {
-                    cpu.keypad().on_up(scancode);
+                } if playback.is_none() => {
+                    if let Some(keycode) = decode_scancode(scancode) {
+                        cpu.keypad().on_up(keycode);
+                    }
                 }
                 _ => {}
             }
         }
 
-        // Tick CPU if needed
-        if cpu_timer.elapsed() > cpu_tick_duration {
-            let raw_instruction = cpu.fetch();
-            let instruction = cpu.decode(raw_instruction);
-            cpu.execute(instruction);
-            cpu_timer = Instant::now();
-
-            cpu_tick_times.push(last_cpu_tick.elapsed());
-            last_cpu_tick = Instant::now();
-            beeper.set_beeping(cpu.should_play_sound());
+        if let Some(stub) = &mut gdb {
+            match stub.poll(&mut cpu, &mut debugger).map_err(|e| e.to_string())? {
+                Session::Attached => {}
+                Session::Detached => {
+                    println!("GDB detached");
+                    gdb = None;
+                }
+                Session::Killed => break 'running,
+            }
         }
 
         if sixty_hz_timer.elapsed() < sixty_hz_duration {
             continue;
         }
 
-        // Tick timers
-        cpu.tick_timers();
+        if rewinding {
+            if let Some(rewound) = rewind_buffer.rewind() {
+                // Keep the keys that are physically held rather than the ones held back then
+                let down_mask = cpu.keypad().down_mask();
+                cpu = rewound;
+                cpu.keypad().set_down_mask(down_mask);
+                halted = false;
+            }
+        } else if debugger.is_paused() {
+            // Blocks on stdin, the window is redrawn after every command. GDB instead steps
+            // the CPU from its packets.
+            if gdb.is_none() {
+                if let debugger::Action::Quit = debugger::prompt(&mut debugger, &mut cpu) {
+                    break 'running;
+                }
+            }
+        } else {
+            if let Some(movie) = &playback {
+                match movie.frame(frame_count) {
+                    Some(down_mask) => cpu.keypad().set_down_mask(down_mask),
+                    None if frame_count == movie.len() => println!("Movie finished"),
+                    None => {}
+                }
+            }
+            if let Some(movie) = &mut recording {
+                movie.record_frame(cpu.keypad().down_mask());
+            }
+            let instructions = instructions_in_frame(instructions_per_second, frame_count as u64);
+            frame_count += 1;
+
+            // Run the CPU for one frame
+            if !halted {
+                let frame = run_frame(
+                    &mut cpu,
+                    &mut debugger,
+                    instructions,
+                    args.machine.on_decode_error,
+                );
+                instruction_count += frame.executed;
+                if let Some(error) = frame.error {
+                    halted = true;
+                    canvas
+                        .window_mut()
+                        .set_title(&format!("Chip-8 emulator - halted: {}", error))
+                        .map_err(|e| e.to_string())?;
+                }
+                if cpu.has_exited() {
+                    halted = true;
+                    canvas
+                        .window_mut()
+                        .set_title("Chip-8 emulator - exited")
+                        .map_err(|e| e.to_string())?;
+                }
+                // Hand control back to GDB when the program can no longer run
+                if halted && gdb.is_some() {
+                    debugger.pause();
+                }
+                if debugger.is_paused() {
+                    match &mut gdb {
+                        Some(stub) => stub.report_stop(frame.stop).map_err(|e| e.to_string())?,
+                        None => {
+                            if let Some(reason) = frame.stop {
+                                println!("{}", reason);
+                            }
+                            debugger::print_location(&cpu);
+                        }
+                    }
+                }
+            }
+
+            // Tick timers
+            cpu.tick_timers();
+            beeper.update(cpu.audio_state());
+            if !is_movie {
+                rewind_buffer.push(&cpu);
+            }
+        }
         sixty_hz_timer = Instant::now();
 
         canvas.set_draw_color(Color::RGB(0, 0, 0));
         canvas.clear();
 
+        // Recreate the texture if the program switched resolution
+        let query = texture.query();
+        if query.width != cpu.vram().width as u32 || query.height != cpu.vram().height as u32 {
+            texture = texture_creator
+                .create_texture_streaming(
+                    PixelFormatEnum::RGB24,
+                    cpu.vram().width as u32,
+                    cpu.vram().height as u32,
+                )
+                .map_err(|e| e.to_string())?;
+        }
+
         // Draw VRAM
         texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
             for y in 0..cpu.vram().height {
                 for x in 0..cpu.vram().width {
-                    let color = if cpu.vram().get_cell(x, y) {
-                        Color::RGB(255, 255, 255)
-                    } else {
-                        Color::RGB(0, 0, 0)
-                    };
+                    let color = PALETTE[cpu.vram().get_pixel(x, y) as usize];
                     write_pixel(buffer, pitch, x as usize, y as usize, color);
                 }
             }
@@ -259,5 +590,14 @@ pub fn main() -> Result<(), String> {
         last_frame_end = Instant::now();
     }
 
+    if let (Some(path), Some(movie)) = (&args.record, &recording) {
+        fs::write(path, movie.to_bytes())
+            .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        println!("Recorded {} frames to {}", movie.len(), path);
+    }
+    if let Some(tracer) = debugger.take_tracer() {
+        tracer.finish().map_err(|e| format!("Failed to write trace: {}", e))?;
+    }
+
     Ok(())
 }
@@ -0,0 +1,202 @@
+use chip8_core::cpu::Cpu;
+use chip8_core::cpu::Memory;
+use chip8_core::dump;
+use chip8_core::error::DecodeError;
+use chip8_core::movie::Movie;
+use chip8_core::octo;
+use chip8_core::quirks::MemoryIncrement;
+use chip8_core::quirks::Platform;
+use chip8_core::quirks::Quirks;
+use chip8_core::savestate;
+use chip8_core::trace::Tracer;
+
+use clap::Args;
+use clap::ValueEnum;
+
+use std::fs;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+
+/// What to do when the CPU hits an opcode it cannot decode or execute
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DecodeErrorPolicy {
+    /// Stop executing and show the error
+    Halt,
+    /// Skip the instruction and keep running
+    Noop,
+    /// Dump the CPU state and pause in the debugger, or stop executing when headless
+    Debug,
+}
+
+/// Reports a decode error according to the policy, returning whether the CPU should halt.
+pub fn handle_decode_error(cpu: &Cpu, error: DecodeError, policy: DecodeErrorPolicy) -> bool {
+    match policy {
+        DecodeErrorPolicy::Halt => {
+            eprintln!("Halting: {}", error);
+            true
+        }
+        DecodeErrorPolicy::Noop => {
+            eprintln!("Skipping: {}", error);
+            false
+        }
+        DecodeErrorPolicy::Debug => {
+            eprintln!("Stopped: {}", error);
+            print!("{}", dump::format_registers(cpu));
+            true
+        }
+    }
+}
+
+// Options for creating and running the machine, shared by the frontends
+#[derive(Args)]
+pub struct MachineArgs {
+    /// ROM file, or an Octo source file (.8o) that is compiled first
+    #[arg(long)]
+    pub rom: String,
+    /// Preset quirks of an interpreter: cosmac-vip, chip-48, schip-1.1, xo-chip or modern
+    #[arg(long)]
+    platform: Option<Platform>,
+    /// Shift VY into VX for 8XY6/8XYE, overriding the platform
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    use_copy_shift: Option<bool>,
+    /// Jump to XNN + VX for BXNN, overriding the platform
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    use_offset_jump_quirk: Option<bool>,
+    /// How FX55/FX65 change I: x-plus-one, x or none, overriding the platform
+    #[arg(long)]
+    memory_increment: Option<MemoryIncrement>,
+    /// Reset VF to 0 after 8XY1/8XY2/8XY3, overriding the platform
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    use_vf_reset: Option<bool>,
+    /// Wrap sprites around the screen edges instead of clipping them, overriding the platform
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    use_sprite_wrap: Option<bool>,
+    /// Count collided and clipped sprite rows in VF in hires mode, overriding the platform
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    use_collided_rows: Option<bool>,
+    /// Wait for the next 60 Hz tick after drawing a sprite, overriding the platform
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    use_display_wait: Option<bool>,
+    #[arg(long, default_value_t = 700)]
+    instructions_per_second: u32,
+    #[arg(long, value_enum, default_value_t = DecodeErrorPolicy::Halt)]
+    pub on_decode_error: DecodeErrorPolicy,
+    /// Seed for the CXNN random number generator, random if not given
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Savestate file to restore before running
+    #[arg(long)]
+    load_state: Option<String>,
+    /// Play back a movie file, using its seed, quirks and speed instead of the options given
+    #[arg(long, conflicts_with = "load_state")]
+    pub play: Option<String>,
+    /// Write every executed instruction with the registers and memory it changed to this file
+    #[arg(long)]
+    trace: Option<String>,
+}
+
+/// The machine described by `MachineArgs`, ready to run
+pub struct Machine {
+    pub cpu: Cpu,
+    pub rom: Vec<u8>,
+    pub instructions_per_second: u32,
+    pub playback: Option<Movie>,
+    pub tracer: Option<Tracer<Box<dyn Write>>>,
+}
+
+impl MachineArgs {
+    /// Quirks of the selected platform, with any individually specified quirks applied on top
+    fn quirks(&self) -> Quirks {
+        let mut quirks = match self.platform {
+            Some(platform) => platform.quirks(),
+            None => Quirks::default(),
+        };
+        if let Some(copy_shift) = self.use_copy_shift {
+            quirks.copy_shift = copy_shift;
+        }
+        if let Some(offset_jump) = self.use_offset_jump_quirk {
+            quirks.offset_jump = offset_jump;
+        }
+        if let Some(memory_increment) = self.memory_increment {
+            quirks.memory_increment = memory_increment;
+        }
+        if let Some(vf_reset) = self.use_vf_reset {
+            quirks.vf_reset = vf_reset;
+        }
+        if let Some(sprite_wrap) = self.use_sprite_wrap {
+            quirks.sprite_wrap = sprite_wrap;
+        }
+        if let Some(count_collided_rows) = self.use_collided_rows {
+            quirks.count_collided_rows = count_collided_rows;
+        }
+        if let Some(display_wait) = self.use_display_wait {
+            quirks.display_wait = display_wait;
+        }
+        quirks
+    }
+
+    /// Loads the ROM, movie, savestate and trace file the options name.
+    pub fn start(&self) -> Result<Machine, String> {
+        let rom = load_rom(&self.rom)?;
+        let playback = match &self.play {
+            Some(path) => Some(load_movie_file(path)?),
+            None => None,
+        };
+        let mut instructions_per_second = self.instructions_per_second;
+        let mut cpu = match &playback {
+            Some(movie) => {
+                if !movie.matches_rom(&rom) {
+                    eprintln!("Warning: the movie was recorded with a different ROM");
+                }
+                instructions_per_second = movie.instructions_per_second;
+                movie.start(&rom)?
+            }
+            None => {
+                let memory = Memory::new(rom.clone())?;
+                match self.seed {
+                    Some(seed) => Cpu::with_seed(memory, self.quirks(), seed),
+                    None => Cpu::new(memory, self.quirks()),
+                }
+            }
+        };
+        if let Some(path) = &self.load_state {
+            cpu = load_state_file(path)?;
+        }
+        let tracer = match &self.trace {
+            Some(path) => Some(create_trace_file(path)?),
+            None => None,
+        };
+        Ok(Machine {
+            cpu,
+            rom,
+            instructions_per_second,
+            playback,
+            tracer,
+        })
+    }
+}
+
+pub fn load_state_file(path: &str) -> Result<Cpu, String> {
+    let data = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    savestate::load(&data).map_err(|e| format!("Failed to load {}: {}", path, e))
+}
+
+/// Reads a ROM, compiling Octo sources (`.8o` files) first
+fn load_rom(path: &str) -> Result<Vec<u8>, String> {
+    if path.ends_with(".8o") {
+        return octo::compile_file(Path::new(path)).map_err(|e| e.to_string());
+    }
+    fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))
+}
+
+fn create_trace_file(path: &str) -> Result<Tracer<Box<dyn Write>>, String> {
+    let file = File::create(path).map_err(|e| format!("Failed to create {}: {}", path, e))?;
+    Ok(Tracer::new(Box::new(BufWriter::new(file))))
+}
+
+fn load_movie_file(path: &str) -> Result<Movie, String> {
+    let data = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    Movie::from_bytes(&data).map_err(|e| format!("Failed to load {}: {}", path, e))
+}